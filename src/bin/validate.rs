@@ -0,0 +1,54 @@
+// Checks that levels can be beaten, by searching for a run through each with
+// the real physics. Validates the built in levels, or the exported level
+// files given as arguments. Exits with 1 if any level can't be beaten.
+use runner_game::master::Master;
+use runner_game::level::Level;
+use runner_game::solver::{self, Failure};
+
+fn check(name: &str, level: &Level) -> bool {
+    match solver::solve(level) {
+        Ok(solution) => {
+            println!("{}: beatable, par {:.2}s ({} ticks, {} states searched)", name, solution.par_time() / 1000., solution.ticks, solution.explored);
+            true
+        },
+        Err(Failure::BuriedGoal) => {
+            println!("{}: the win block has no room to stand in", name);
+            false
+        },
+        Err(Failure::NoRoute) => {
+            println!("{}: no way through found", name);
+            false
+        },
+        Err(Failure::ReplayMismatch) => {
+            println!("{}: the way through found didn't play back the same", name);
+            false
+        },
+    }
+}
+
+fn main() {
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    let mut all_beatable = true;
+    if paths.is_empty() {
+        let master = Master::new();
+        for index in 0..master.num_levels() {
+            if let Some(level) = master.level(index) {
+                all_beatable &= check(&format!("level {}", index + 1), level);
+            }
+        }
+    } else {
+        for path in &paths {
+            let level = std::fs::read_to_string(path).ok().and_then(|data| Level::deserialize(&data));
+            match level {
+                Some(level) => all_beatable &= check(path, &level),
+                None => {
+                    println!("{}: couldn't read level", path);
+                    all_beatable = false;
+                },
+            }
+        }
+    }
+    if !all_beatable {
+        std::process::exit(1);
+    }
+}
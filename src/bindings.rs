@@ -0,0 +1,259 @@
+use wasm_bindgen::prelude::*;
+use super::universe::Input;
+use super::snapshot::{StateWriter, StateReader};
+
+// What a player can do, whatever they press for it.
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Action {
+    MoveLeft = 0,
+    MoveForward = 1,
+    MoveRight = 2,
+    MoveBack = 3,
+    Jump = 4,
+    Cast = 5,
+    Pull = 6,
+    CastRight = 7,
+    PullRight = 8,
+    // held to turn, like pushing the look stick all the way
+    LookLeft = 9,
+    LookRight = 10,
+    LookUp = 11,
+    LookDown = 12,
+    // for the page to handle, not the universe
+    Pause = 13,
+    Restart = 14,
+    ToggleAimAssist = 15,
+}
+
+impl Action {
+    pub fn from_u8(code: u8) -> Option<Action> {
+        ALL_ACTIONS.get(code as usize).copied()
+    }
+
+    // The universe input for pressing or letting go, if the universe handles it.
+    pub fn input(self, pressed: bool) -> Option<Input> {
+        match (self, pressed) {
+            (Action::MoveLeft, true) => Some(Input::GoLeft),
+            (Action::MoveForward, true) => Some(Input::GoForward),
+            (Action::MoveRight, true) => Some(Input::GoRight),
+            (Action::MoveBack, true) => Some(Input::GoBack),
+            (Action::MoveLeft, false) => Some(Input::StopLeft),
+            (Action::MoveForward, false) => Some(Input::StopForward),
+            (Action::MoveRight, false) => Some(Input::StopRight),
+            (Action::MoveBack, false) => Some(Input::StopBack),
+            (Action::Jump, true) => Some(Input::Jump),
+            (Action::Cast, true) => Some(Input::Cast),
+            (Action::CastRight, true) => Some(Input::CastRight),
+            (Action::Pull, pressed) => Some(if pressed { Input::Pull } else { Input::Release }),
+            (Action::PullRight, pressed) => Some(if pressed { Input::PullRight } else { Input::ReleaseRight }),
+            (Action::LookLeft, true) => Some(Input::LookLeft),
+            (Action::LookRight, true) => Some(Input::LookRight),
+            (Action::LookUp, true) => Some(Input::LookUp),
+            (Action::LookDown, true) => Some(Input::LookDown),
+            (Action::LookLeft, false) => Some(Input::StopLookLeft),
+            (Action::LookRight, false) => Some(Input::StopLookRight),
+            (Action::LookUp, false) => Some(Input::StopLookUp),
+            (Action::LookDown, false) => Some(Input::StopLookDown),
+            _ => None,
+        }
+    }
+}
+
+pub const ALL_ACTIONS: [Action; 16] = [
+    Action::MoveLeft, Action::MoveForward, Action::MoveRight, Action::MoveBack,
+    Action::Jump, Action::Cast, Action::Pull, Action::CastRight, Action::PullRight,
+    Action::LookLeft, Action::LookRight, Action::LookUp, Action::LookDown,
+    Action::Pause, Action::Restart, Action::ToggleAimAssist,
+];
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Device {
+    Key = 0,
+    Mouse = 1,
+    Gamepad = 2,
+}
+
+// Something physical to press: a key by its KeyboardEvent.code, so bindings
+// don't move with the keyboard layout, or a mouse or standard gamepad button.
+#[wasm_bindgen]
+#[derive(Clone, PartialEq, Debug)]
+pub struct Control {
+    device: Device,
+    code: String, // keys
+    button: u32, // mouse and gamepad
+}
+
+#[wasm_bindgen]
+impl Control {
+    pub fn key(code: &str) -> Control {
+        Control { device: Device::Key, code: code.to_string(), button: 0 }
+    }
+
+    pub fn mouse(button: u32) -> Control {
+        Control { device: Device::Mouse, code: String::new(), button }
+    }
+
+    pub fn gamepad(button: u32) -> Control {
+        Control { device: Device::Gamepad, code: String::new(), button }
+    }
+
+    pub fn device(&self) -> Device {
+        self.device
+    }
+
+    // For showing in the controls menu, e.g. "KeyW", "Mouse 0" or "Pad 7".
+    pub fn name(&self) -> String {
+        match self.device {
+            Device::Key => self.code.clone(),
+            Device::Mouse => format!("Mouse {}", self.button),
+            Device::Gamepad => format!("Pad {}", self.button),
+        }
+    }
+}
+
+// A control bound to one player's action. Gamepads each drive whichever player
+// they're assigned to, so gamepad bindings are shared and their player is 0.
+#[derive(Clone)]
+pub struct Binding {
+    pub control: Control,
+    pub player: usize,
+    pub action: Action,
+}
+
+// What a control is bound to, if anything.
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct Bound {
+    pub found: bool,
+    pub player: usize,
+    pub action: Action,
+}
+
+impl Bound {
+    fn none() -> Bound {
+        Bound { found: false, player: 0, action: Action::MoveLeft }
+    }
+}
+
+// The binding table. Each control does at most one thing, and each player's
+// action has at most one control per device.
+#[derive(Clone)]
+pub struct Bindings {
+    bindings: Vec<Binding>,
+}
+
+impl Bindings {
+    // Player 0 on WASD and the mouse, player 1 on the arrows and IJKL, and
+    // gamepads on their triggers and bumpers.
+    pub fn defaults() -> Self {
+        let mut bindings = Bindings { bindings: vec![] };
+        let keys = [
+            (0, "KeyA", Action::MoveLeft), (0, "KeyW", Action::MoveForward), (0, "KeyD", Action::MoveRight), (0, "KeyS", Action::MoveBack),
+            (0, "Space", Action::Jump), (0, "KeyE", Action::CastRight), (0, "KeyQ", Action::PullRight),
+            (0, "KeyM", Action::Pause), (0, "KeyR", Action::Restart), (0, "KeyG", Action::ToggleAimAssist),
+            (1, "ArrowLeft", Action::MoveLeft), (1, "ArrowUp", Action::MoveForward), (1, "ArrowRight", Action::MoveRight), (1, "ArrowDown", Action::MoveBack),
+            (1, "Enter", Action::Jump), (1, "Slash", Action::Cast), (1, "Period", Action::Pull),
+            (1, "KeyJ", Action::LookLeft), (1, "KeyL", Action::LookRight), (1, "KeyI", Action::LookUp), (1, "KeyK", Action::LookDown),
+        ];
+        for (player, code, action) in keys.iter() {
+            bindings.bind(*player, *action, Control::key(code));
+        }
+        bindings.bind(0, Action::Cast, Control::mouse(0));
+        bindings.bind(0, Action::Pull, Control::mouse(2));
+        let buttons = [(0, Action::Jump), (7, Action::Cast), (6, Action::Pull), (5, Action::CastRight), (4, Action::PullRight), (9, Action::Pause)];
+        for (button, action) in buttons.iter() {
+            bindings.bind(0, *action, Control::gamepad(*button));
+        }
+        bindings
+    }
+
+    pub fn lookup(&self, control: &Control) -> Bound {
+        match self.bindings.iter().find(|binding| binding.control == *control) {
+            Some(binding) => Bound { found: true, player: binding.player, action: binding.action },
+            None => Bound::none(),
+        }
+    }
+
+    // The player's control for an action on a device. Keys and mouse buttons
+    // count as one device, so that's what either returns.
+    pub fn control(&self, player: usize, action: Action, device: Device) -> Option<&Control> {
+        let gamepad = device == Device::Gamepad;
+        self.bindings.iter()
+            .find(|binding| binding.player == player && binding.action == action && (binding.control.device == Device::Gamepad) == gamepad)
+            .map(|binding| &binding.control)
+    }
+
+    // Binds the control, replacing the action's old one on that device. If the
+    // control already does something else, nothing changes and that's returned.
+    // Key codes are saved as one word, so one that's empty or has spaces in it
+    // is never bound.
+    pub fn bind(&mut self, player: usize, action: Action, control: Control) -> Bound {
+        if control.device == Device::Key && (control.code.is_empty() || control.code.contains(char::is_whitespace)) {
+            return Bound::none()
+        }
+        let player = if control.device == Device::Gamepad { 0 } else { player };
+        let conflict = self.lookup(&control);
+        if conflict.found && (conflict.player != player || conflict.action != action) {
+            return conflict
+        }
+        let gamepad = control.device == Device::Gamepad;
+        self.bindings.retain(|binding| !(binding.player == player && binding.action == action && (binding.control.device == Device::Gamepad) == gamepad));
+        self.bindings.push(Binding { control, player, action });
+        Bound::none()
+    }
+
+    pub fn unbind(&mut self, control: &Control) {
+        self.bindings.retain(|binding| binding.control != *control);
+    }
+
+    pub fn write_state(&self, writer: &mut StateWriter) {
+        writer.usize(self.bindings.len());
+        for binding in &self.bindings {
+            writer.u32(binding.control.device as u32);
+            match binding.control.device {
+                Device::Key => writer.word(&binding.control.code),
+                Device::Mouse | Device::Gamepad => writer.u32(binding.control.button),
+            }
+            writer.usize(binding.player);
+            writer.u32(binding.action as u32);
+        }
+    }
+
+    pub fn read_state(reader: &mut StateReader) -> Option<Self> {
+        let mut bindings = vec![];
+        for _binding in 0..reader.usize()? {
+            let control = match reader.u32()? {
+                0 => Control::key(reader.word()?),
+                1 => Control::mouse(reader.u32()?),
+                2 => Control::gamepad(reader.u32()?),
+                _ => return None,
+            };
+            let player = reader.usize()?;
+            let action = Action::from_u8(reader.u32()? as u8)?;
+            bindings.push(Binding { control, player, action });
+        }
+        Some(Bindings { bindings })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_codes_that_arent_one_word_are_never_bound_or_saved() {
+        let mut bindings = Bindings::defaults();
+        for code in ["", " ", "Key W"].iter() {
+            bindings.bind(0, Action::Jump, Control::key(code));
+            assert!(!bindings.lookup(&Control::key(code)).found);
+        }
+        let mut writer = StateWriter::new();
+        bindings.write_state(&mut writer);
+        let saved = writer.finish();
+        let loaded = Bindings::read_state(&mut StateReader::new(&saved)).unwrap();
+        assert_eq!(loaded.bindings.len(), Bindings::defaults().bindings.len());
+        assert!(loaded.lookup(&Control::key("Space")).action == Action::Jump);
+    }
+}
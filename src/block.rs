@@ -5,13 +5,15 @@ use super::utils::AABB;
 pub struct Block {
     pub origin: Vec3,
     pub dims: Vec3,
+    pub material: usize, // index into the level's palette
 }
 
 impl Block {
-    pub fn new(origin: Vec3, dims: Vec3) -> Self {
+    pub fn new(origin: Vec3, dims: Vec3, material: usize) -> Self {
         Self {
             origin,
             dims,
+            material,
         }
     }
 }
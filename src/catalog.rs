@@ -0,0 +1,224 @@
+use wasm_bindgen::prelude::*;
+use super::save::SaveData;
+use super::replay::Replay;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Medal {
+    None = 0,
+    Bronze = 1,
+    Silver = 2,
+    Gold = 3,
+}
+
+impl Medal {
+    pub fn from_u8(code: u8) -> Option<Medal> {
+        match code {
+            0 => Some(Medal::None),
+            1 => Some(Medal::Bronze),
+            2 => Some(Medal::Silver),
+            3 => Some(Medal::Gold),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Entry {
+    pub id: String, // stable across releases, unlike positions
+    pub name: String,
+    pub description: String,
+    pub level: usize, // index into Master's levels
+    pub par_time: f32, // ms
+    pub medal_times: [f32; 3], // bronze, silver, gold, in ms
+    // unlocks early once this many levels have at least this medal,
+    // without finishing the one before
+    pub unlock_medals: Option<(Medal, usize)>,
+}
+
+impl Entry {
+    pub fn medal(&self, time: f32) -> Medal {
+        let medals = [Medal::Gold, Medal::Silver, Medal::Bronze];
+        for (medal, limit) in medals.iter().zip(self.medal_times.iter().rev()) {
+            if time <= *limit {
+                return *medal
+            }
+        }
+        Medal::None
+    }
+}
+
+pub struct Pack {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub entries: Vec<Entry>,
+}
+
+// The levels the menu offers, in order. How far through them the player is
+// comes from their save data.
+pub struct Catalog {
+    packs: Vec<Pack>,
+}
+
+impl Catalog {
+    pub fn new(packs: Vec<Pack>) -> Self {
+        Self {
+            packs,
+        }
+    }
+
+    pub fn packs(&self) -> &Vec<Pack> {
+        &self.packs
+    }
+
+    pub fn entry(&self, pack: usize, entry: usize) -> Option<&Entry> {
+        self.packs.get(pack)?.entries.get(entry)
+    }
+
+    // The entry that plays a level, if it's in the catalog at all.
+    pub fn find(&self, level: usize) -> Option<(usize, usize)> {
+        for (pack_index, pack) in self.packs.iter().enumerate() {
+            if let Some(entry_index) = pack.entries.iter().position(|entry| entry.level == level) {
+                return Some((pack_index, entry_index))
+            }
+        }
+        None
+    }
+
+    // Levels unlock in order, each when the one before it is finished, the
+    // first of a pack following the last of the pack before. Once unlocked
+    // they stay that way, even if the catalog changes.
+    pub fn unlocked(&self, save: &SaveData, pack: usize, entry: usize) -> bool {
+        let unlock_medals = match self.entry(pack, entry) {
+            Some(info) if save.unlocked(&info.id) => return true,
+            Some(info) => info.unlock_medals,
+            None => return false,
+        };
+        let previous = if entry > 0 {
+            self.entry(pack, entry - 1)
+        } else if pack > 0 {
+            self.packs[pack - 1].entries.last()
+        } else {
+            None
+        };
+        if previous.is_none_or(|previous| save.record(&previous.id).is_some()) {
+            return true
+        }
+        match unlock_medals {
+            Some((medal, count)) => {
+                let earned = self.packs.iter()
+                    .flat_map(|pack| pack.entries.iter())
+                    .filter(|entry| save.record(&entry.id).is_some_and(|record| record.medal >= medal))
+                    .count();
+                earned >= count
+            },
+            None => false,
+        }
+    }
+
+    pub fn pack_info(&self, save: &SaveData, pack: usize) -> Option<PackInfo> {
+        let info = self.packs.get(pack)?;
+        Some(PackInfo {
+            id: info.id.clone(),
+            name: info.name.clone(),
+            description: info.description.clone(),
+            levels: info.entries.len(),
+            unlocked: self.unlocked(save, pack, 0),
+        })
+    }
+
+    pub fn level_info(&self, save: &SaveData, pack: usize, entry: usize) -> Option<LevelInfo> {
+        let info = self.entry(pack, entry)?;
+        let record = save.record(&info.id);
+        Some(LevelInfo {
+            id: info.id.clone(),
+            name: info.name.clone(),
+            description: info.description.clone(),
+            level: info.level,
+            par_time: info.par_time,
+            bronze_time: info.medal_times[0],
+            silver_time: info.medal_times[1],
+            gold_time: info.medal_times[2],
+            unlocked: self.unlocked(save, pack, entry),
+            best_time: record.map_or(0., |record| record.time),
+            medal: record.map_or(Medal::None, |record| record.medal),
+        })
+    }
+
+    // Saves a finish if it's the best, unlocks what it opens up, and returns
+    // the medal it earned. Levels outside the catalog don't earn medals.
+    pub fn finish(&self, save: &mut SaveData, level: usize, time: f32, replay: Option<Replay>) -> Medal {
+        let (pack, entry) = match self.find(level) {
+            Some(found) => found,
+            None => return Medal::None,
+        };
+        let entry = &self.packs[pack].entries[entry];
+        let medal = entry.medal(time);
+        save.finish(&entry.id, time, medal, replay);
+        for (pack_index, pack) in self.packs.iter().enumerate() {
+            for (entry_index, entry) in pack.entries.iter().enumerate() {
+                if self.unlocked(save, pack_index, entry_index) {
+                    save.unlock(&entry.id);
+                }
+            }
+        }
+        medal
+    }
+}
+
+// A pack as the menu shows it.
+#[wasm_bindgen]
+pub struct PackInfo {
+    id: String,
+    name: String,
+    description: String,
+    pub levels: usize,
+    pub unlocked: bool, // whether its first level is
+}
+
+#[wasm_bindgen]
+impl PackInfo {
+    pub fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn description(&self) -> String {
+        self.description.clone()
+    }
+}
+
+// A level as the menu shows it. Times are in ms, and best_time is 0 until it's finished.
+#[wasm_bindgen]
+pub struct LevelInfo {
+    id: String,
+    name: String,
+    description: String,
+    pub level: usize, // what to pass to Master::start
+    pub par_time: f32,
+    pub bronze_time: f32,
+    pub silver_time: f32,
+    pub gold_time: f32,
+    pub unlocked: bool,
+    pub best_time: f32,
+    pub medal: Medal,
+}
+
+#[wasm_bindgen]
+impl LevelInfo {
+    pub fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn description(&self) -> String {
+        self.description.clone()
+    }
+}
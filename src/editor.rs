@@ -0,0 +1,370 @@
+use wasm_bindgen::prelude::*;
+use super::level::Level;
+use super::utils::Vec3;
+use super::utils::AABB;
+use super::block::Block;
+use super::palette::Palette;
+use super::rules::Rules;
+use super::graphics::{Graphics, graphics_accessors};
+use super::universe::TICK_TIME;
+use super::player::LOOK_SPEED;
+
+const FLY_SPEED: f32 = 0.25; // per tick
+const PICK_RANGE: f32 = 150.;
+const PLACE_DISTANCE: f32 = 6.; // where new blocks go when the crosshair hits nothing
+const MAX_UNDO: usize = 100;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub enum Fly {
+    Left = 0,
+    Forward = 1,
+    Right = 2,
+    Back = 3,
+    Up = 4,
+    Down = 5,
+}
+
+// Everything an edit can change, kept whole for undo and redo.
+#[derive(Clone)]
+struct EditState {
+    blocks: Vec<Block>,
+    win_block: Block,
+    start_pos: Vec3,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Selection {
+    Block(usize),
+    Win,
+}
+
+#[wasm_bindgen]
+pub struct Editor {
+    state: EditState,
+    undo: Vec<EditState>,
+    redo: Vec<EditState>,
+    selected: Option<Selection>,
+    grid: f32,
+    material: usize, // for new blocks
+
+    // carried through to the exported level as is
+    palette: Palette,
+    rules: Rules,
+    checkpoints: Vec<Block>,
+
+    cam_pos: Vec3,
+    cam_theta: f32,
+    cam_phi: f32,
+    flying: [bool; 6], // indexed by Fly
+
+    graphics: Graphics,
+}
+
+impl Editor {
+    pub fn new(level: &Level) -> Self {
+        let mut graphics = Graphics::new();
+        graphics.set_light(Vec3::new(-0.4, -1., 0.3), 0.45);
+        Self {
+            state: EditState {
+                blocks: level.blocks(),
+                win_block: level.win_block(),
+                start_pos: level.start_pos(),
+            },
+            undo: vec![],
+            redo: vec![],
+            selected: None,
+            grid: 0.5,
+            material: 0,
+            palette: level.palette(),
+            rules: level.rules(),
+            checkpoints: level.checkpoints(),
+            cam_pos: level.start_pos() + Vec3::new(0., 3., -6.),
+            cam_theta: 0.,
+            cam_phi: 0.,
+            flying: [false; 6],
+            graphics,
+        }
+    }
+
+    // A floor to build on, with the goal a short way ahead.
+    pub fn blank() -> Self {
+        Self::new(&Level::new(
+            vec![-10., -1., -10., 20., 1., 20., 0.],
+            Block::new(Vec3::new(-1.5, 0., 6.), Vec3::new(3., 3., 2.), 0),
+            Vec3::new(0., 1.5, -5.),
+            Palette::classic(),
+            Rules::non_contact(),
+            vec![],
+        ))
+    }
+
+    pub fn level(&self) -> Level {
+        Level::new(
+            Level::block_data_from(&self.state.blocks),
+            self.state.win_block.clone(),
+            self.state.start_pos,
+            self.palette.clone(),
+            self.rules,
+            self.checkpoints.clone(),
+        )
+    }
+
+    // The level as it is, but starting wherever the camera is.
+    pub fn playtest_level(&self) -> Level {
+        Level::new(
+            Level::block_data_from(&self.state.blocks),
+            self.state.win_block.clone(),
+            self.cam_pos,
+            self.palette.clone(),
+            self.rules,
+            self.checkpoints.clone(),
+        )
+    }
+
+    fn look_dir(&self) -> Vec3 {
+        Vec3::new(self.cam_theta.sin() * self.cam_phi.cos(), self.cam_phi.sin(), self.cam_theta.cos() * self.cam_phi.cos())
+    }
+
+    fn snap(&self, value: f32) -> f32 {
+        (value / self.grid).round() * self.grid
+    }
+
+    fn snap_vec(&self, value: Vec3) -> Vec3 {
+        Vec3::new(self.snap(value.x), self.snap(value.y), self.snap(value.z))
+    }
+
+    // Call before every change, so it can be undone.
+    fn save_undo(&mut self) {
+        self.undo.push(self.state.clone());
+        if self.undo.len() > MAX_UNDO {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    fn selected_block(&mut self) -> Option<&mut Block> {
+        match self.selected? {
+            Selection::Block(index) => self.state.blocks.get_mut(index),
+            Selection::Win => Some(&mut self.state.win_block),
+        }
+    }
+
+    // The nearest block or win volume under the crosshair, and how far away.
+    fn pick_ray(&self) -> Option<(Selection, f32)> {
+        let dir = self.look_dir();
+        let mut nearest: Option<(Selection, f32)> = None;
+        let candidates = self.state.blocks.iter().enumerate()
+            .map(|(index, block)| (Selection::Block(index), block))
+            .chain(std::iter::once((Selection::Win, &self.state.win_block)));
+        for (selection, block) in candidates {
+            if let Some(distance) = block.ray_hit(&self.cam_pos, &dir) {
+                if distance <= PICK_RANGE && nearest.is_none_or(|(_, nearest)| distance < nearest) {
+                    nearest = Some((selection, distance));
+                }
+            }
+        }
+        nearest
+    }
+
+    fn update_graphics(&mut self) {
+        let graphics = &mut self.graphics;
+        graphics.clear();
+        graphics.set_camera(self.cam_pos, self.cam_theta, -self.cam_phi);
+        graphics.set_view_camera(0, self.cam_pos, self.cam_theta, -self.cam_phi);
+
+        // selected blocks are drawn washed out toward white
+        let highlight = |color: [f32; 4], selected: bool| {
+            if !selected {
+                return color
+            }
+            [(color[0] + 1.) / 2., (color[1] + 1.) / 2., (color[2] + 1.) / 2., color[3]]
+        };
+
+        for (index, block) in self.state.blocks.iter().enumerate() {
+            let color = highlight(self.palette.material(block.material), self.selected == Some(Selection::Block(index)));
+            graphics.push_block(&block.origin, &block.dims, &color);
+        }
+        let win = &self.state.win_block;
+        graphics.push_block(&win.origin, &win.dims, &highlight(self.palette.win(), self.selected == Some(Selection::Win)));
+
+        // the spawn point, as a player sized box
+        let player_dims = Vec3::new(0.5, 2., 0.5);
+        graphics.push_block(&(self.state.start_pos - player_dims / 2.), &player_dims, &self.palette.player(0));
+    }
+}
+
+#[wasm_bindgen]
+impl Editor {
+    pub fn update(&mut self, elapsed_time: f32) {
+        let time_step = elapsed_time / TICK_TIME;
+        let forward = Vec3::new(self.cam_theta.sin(), 0., self.cam_theta.cos());
+        let right = Vec3::new(self.cam_theta.cos(), 0., -self.cam_theta.sin());
+        let up = Vec3::new(0., 1., 0.);
+        let dirs = [right * -1., forward, right, forward * -1., up, up * -1.];
+        let mut movement = Vec3::new(0., 0., 0.);
+        for (dir, flying) in dirs.iter().zip(self.flying.iter()) {
+            if *flying {
+                movement += *dir;
+            }
+        }
+        if movement.length() > 0. {
+            self.cam_pos += movement.unit() * FLY_SPEED * time_step;
+        }
+        self.update_graphics();
+    }
+
+    pub fn start_fly(&mut self, fly: Fly) {
+        self.flying[fly as usize] = true;
+    }
+
+    pub fn stop_fly(&mut self, fly: Fly) {
+        self.flying[fly as usize] = false;
+    }
+
+    pub fn mouse_look(&mut self, movement_x: f32, movement_y: f32) {
+        self.cam_theta += movement_x * LOOK_SPEED;
+        let phi = self.cam_phi - movement_y * LOOK_SPEED;
+        if phi.abs() < std::f32::consts::PI / 2. {
+            self.cam_phi = phi;
+        }
+    }
+
+    // Selects what's under the crosshair, or clears the selection.
+    pub fn pick(&mut self) -> bool {
+        self.selected = self.pick_ray().map(|(selection, _)| selection);
+        self.selected.is_some()
+    }
+
+    pub fn has_selection(&self) -> bool {
+        self.selected.is_some()
+    }
+
+    // A grid sized block just in front of whatever the crosshair is on.
+    pub fn create_block(&mut self) {
+        let dir = self.look_dir();
+        let distance = self.pick_ray().map_or(PLACE_DISTANCE, |(_, distance)| distance - self.grid / 2.);
+        let center = self.cam_pos + dir * distance;
+        let dims = Vec3::new(self.grid, self.grid, self.grid);
+        let origin = self.snap_vec(center - dims / 2.);
+        self.save_undo();
+        self.state.blocks.push(Block::new(origin, dims, self.material));
+        self.selected = Some(Selection::Block(self.state.blocks.len() - 1));
+    }
+
+    // In grid steps.
+    pub fn move_selected(&mut self, dx: i32, dy: i32, dz: i32) {
+        if self.selected.is_none() {
+            return
+        }
+        let grid = self.grid;
+        self.save_undo();
+        if let Some(block) = self.selected_block() {
+            block.origin += Vec3::new(dx as f32, dy as f32, dz as f32) * grid;
+        }
+    }
+
+    // In grid steps, growing away from the origin. Never smaller than a grid step.
+    pub fn resize_selected(&mut self, dx: i32, dy: i32, dz: i32) {
+        if self.selected.is_none() {
+            return
+        }
+        let grid = self.grid;
+        self.save_undo();
+        if let Some(block) = self.selected_block() {
+            let dims = block.dims + Vec3::new(dx as f32, dy as f32, dz as f32) * grid;
+            block.dims = Vec3::new(dims.x.max(grid), dims.y.max(grid), dims.z.max(grid));
+        }
+    }
+
+    // The copy goes beside the original, on whichever side is to the camera's right.
+    pub fn duplicate_selected(&mut self) {
+        let right = Vec3::new(self.cam_theta.cos(), 0., -self.cam_theta.sin());
+        let block = match self.selected {
+            Some(Selection::Block(index)) => self.state.blocks[index].clone(),
+            _ => return,
+        };
+        let offset = if right.x.abs() >= right.z.abs() {
+            Vec3::new(block.dims.x * right.x.signum(), 0., 0.)
+        } else {
+            Vec3::new(0., 0., block.dims.z * right.z.signum())
+        };
+        self.save_undo();
+        self.state.blocks.push(Block::new(block.origin + offset, block.dims, block.material));
+        self.selected = Some(Selection::Block(self.state.blocks.len() - 1));
+    }
+
+    // The win volume can be moved and resized, but not deleted.
+    pub fn delete_selected(&mut self) {
+        if let Some(Selection::Block(index)) = self.selected {
+            self.save_undo();
+            self.state.blocks.remove(index);
+            self.selected = None;
+        }
+    }
+
+    // Sets the material of the selected block, and of new blocks.
+    pub fn cycle_material(&mut self) {
+        self.material += 1;
+        let material = self.material;
+        if let Some(Selection::Block(index)) = self.selected {
+            self.save_undo();
+            self.state.blocks[index].material = material;
+        }
+    }
+
+    // Turns the selected block into the win volume, replacing the old one.
+    pub fn set_win(&mut self) {
+        if let Some(Selection::Block(index)) = self.selected {
+            self.save_undo();
+            self.state.win_block = self.state.blocks.remove(index);
+            self.selected = Some(Selection::Win);
+        }
+    }
+
+    pub fn set_spawn(&mut self) {
+        self.save_undo();
+        self.state.start_pos = self.snap_vec(self.cam_pos);
+    }
+
+    pub fn set_grid(&mut self, grid: f32) {
+        self.grid = grid.max(0.05);
+    }
+
+    pub fn grid(&self) -> f32 {
+        self.grid
+    }
+
+    pub fn undo(&mut self) -> bool {
+        match self.undo.pop() {
+            Some(state) => {
+                self.redo.push(std::mem::replace(&mut self.state, state));
+                self.selected = None;
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn redo(&mut self) -> bool {
+        match self.redo.pop() {
+            Some(state) => {
+                self.undo.push(std::mem::replace(&mut self.state, state));
+                self.selected = None;
+                true
+            },
+            None => false,
+        }
+    }
+
+    // In the format Level::deserialize and Master::add_level read.
+    pub fn export(&self) -> String {
+        self.level().serialize()
+    }
+
+    pub fn local_players(&self) -> usize {
+        1
+    }
+}
+
+graphics_accessors!(Editor);
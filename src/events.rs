@@ -0,0 +1,83 @@
+use wasm_bindgen::prelude::*;
+use super::utils::Vec3;
+use super::grapple::Hand;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq)]
+pub enum EventKind {
+    Jumped,
+    Landed,
+    GrappleCast,
+    GrappleHooked,
+    GrappleBroke,
+    CheckpointReached,
+    Died,
+    LevelWon,
+    Started, // the countdown hit go
+}
+
+// A game moment for JS to react to. Fields that don't apply to the kind are 0.
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct Event {
+    pub kind: EventKind,
+    pub player: usize,
+    pub hand: Hand, // grapple events
+    pub impact_speed: f32, // Landed
+    pub x: f32, // GrappleHooked point
+    pub y: f32,
+    pub z: f32,
+    pub checkpoint: usize, // CheckpointReached
+}
+
+impl Event {
+    pub fn new(kind: EventKind) -> Self {
+        Self {
+            kind,
+            player: 0,
+            hand: Hand::Left,
+            impact_speed: 0.,
+            x: 0.,
+            y: 0.,
+            z: 0.,
+            checkpoint: 0,
+        }
+    }
+
+    pub fn landed(impact_speed: f32) -> Self {
+        Self {
+            impact_speed,
+            ..Self::new(EventKind::Landed)
+        }
+    }
+
+    pub fn hooked(point: Vec3) -> Self {
+        Self {
+            x: point.x,
+            y: point.y,
+            z: point.z,
+            ..Self::new(EventKind::GrappleHooked)
+        }
+    }
+
+    pub fn checkpoint(checkpoint: usize) -> Self {
+        Self {
+            checkpoint,
+            ..Self::new(EventKind::CheckpointReached)
+        }
+    }
+
+    pub fn for_hand(self, hand: Hand) -> Self {
+        Self {
+            hand,
+            ..self
+        }
+    }
+
+    pub fn for_player(self, player: usize) -> Self {
+        Self {
+            player,
+            ..self
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use wasm_bindgen::prelude::*;
+use super::level::Level;
+use super::utils::Vec3;
+use super::utils::AABB;
+use super::block::Block;
+use super::palette::Palette;
+use super::rules::Rules;
+use super::player::{Player, Go, JUMP_SPEED};
+use super::grapple::GRAPPLE_LENGTH;
+use super::universe::{GRAVITY, TICK_TIME};
+use super::solver;
+
+const SAFETY: f32 = 0.8; // never ask for more than this share of the player's reach
+const PLAYER_HEIGHT: f32 = 2.;
+const PLATFORM_THICKNESS: f32 = 0.5;
+const MAX_ATTEMPTS: u32 = 16;
+const CHECKPOINT_EVERY: usize = 4; // segments
+
+// Knobs from 0 (gentle) to 1 (as hard as still provably possible).
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct Difficulty {
+    pub segments: usize,
+    pub gap: f32, // how much of the jump reach gaps use
+    pub climb: f32, // how much of the jump height steps up use
+    pub grapple: f32, // share of segments that need the grapple
+    pub platform: f32, // how small platforms get
+}
+
+#[wasm_bindgen]
+impl Difficulty {
+    pub fn new(segments: usize, gap: f32, climb: f32, grapple: f32, platform: f32) -> Difficulty {
+        let clamp = |value: f32| if value.is_nan() { 0. } else { value.clamp(0., 1.) };
+        Difficulty {
+            segments: segments.max(1),
+            gap: clamp(gap),
+            climb: clamp(climb),
+            grapple: clamp(grapple),
+            platform: clamp(platform),
+        }
+    }
+
+    pub fn easy() -> Difficulty {
+        Difficulty::new(8, 0.3, 0.3, 0.15, 0.2)
+    }
+
+    pub fn normal() -> Difficulty {
+        Difficulty::new(12, 0.6, 0.6, 0.3, 0.5)
+    }
+
+    pub fn hard() -> Difficulty {
+        Difficulty::new(16, 0.9, 0.9, 0.5, 0.8)
+    }
+}
+
+// xorshift32, so the same seed makes the same level everywhere
+struct Rng {
+    state: u32,
+}
+
+impl Rng {
+    fn new(seed: u32) -> Self {
+        let state = seed.wrapping_mul(0x9e3779b9) ^ 0x85ebca6b;
+        // zero is a fixed point of xorshift
+        Self { state: if state == 0 { 1 } else { state } }
+    }
+
+    fn next(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+
+    // in [0, 1)
+    fn float(&mut self) -> f32 {
+        (self.next() >> 8) as f32 / (1 << 24) as f32
+    }
+
+    fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.float()
+    }
+}
+
+// How a segment is crossed, for the verifier.
+#[derive(Clone, Copy, PartialEq)]
+enum Hop {
+    Jump,
+    Grapple(usize), // index of the anchor block
+}
+
+// Platforms in course order, what gets you from each to the next, and anchors.
+struct Course {
+    platforms: Vec<Block>,
+    hops: Vec<Hop>,
+    anchors: Vec<Block>,
+}
+
+// Jumps a real player forward from standing still, and returns how far it
+// carries before dropping below `rise`. None if it never gets that high.
+// Standing still is the shortest jump, as running speed builds up from there.
+pub fn jump_reach(rise: f32) -> Option<f32> {
+    let floor = vec![Block::new(Vec3::new(-5., -1., -5.), Vec3::new(10., 1., 10.), 0)];
+    let mut player = Player::new(Vec3::new(0., PLAYER_HEIGHT / 2. + 0.1, 0.));
+    while !player.on_ground() {
+        player.update(&floor, &[], GRAVITY, TICK_TIME);
+    }
+    let start = player.position;
+    player.go(Go::Forward);
+    player.go(Go::Jump);
+    // off the floor, so it can fall past it
+    let open_air = vec![];
+    let mut reach = None;
+    loop {
+        player.update(&open_air, &[], GRAVITY, TICK_TIME);
+        let height = player.position.y - start.y;
+        if height >= rise {
+            reach = Some(player.position.z - start.z);
+        } else if player.velocity.y < 0. {
+            return reach
+        }
+    }
+}
+
+pub fn jump_height() -> f32 {
+    let mut height = 0.;
+    let mut velocity = JUMP_SPEED;
+    while velocity + GRAVITY > 0. {
+        velocity += GRAVITY;
+        height += velocity;
+    }
+    height
+}
+
+// None if no attempt passed verification, even the easiest ones.
+pub fn generate(seed: u32, difficulty: Difficulty) -> Option<Level> {
+    // a failed attempt moves on to another stream from the same seed, so it's still deterministic
+    let mut rng = Rng::new(seed);
+    // then flat and short enough that every hop should be easily in reach
+    let easiest = Difficulty::new(difficulty.segments, 0., 0., 0., 0.);
+    for attempt in 0..MAX_ATTEMPTS * 2 {
+        let course = build(&mut rng, if attempt < MAX_ATTEMPTS { &difficulty } else { &easiest });
+        if verify(&course) {
+            return Some(to_level(&course))
+        }
+    }
+    None
+}
+
+fn build(rng: &mut Rng, difficulty: &Difficulty) -> Course {
+    let mut platforms = vec![Block::new(Vec3::new(-3., -PLATFORM_THICKNESS, -3.), Vec3::new(6., PLATFORM_THICKNESS, 8.), 0)];
+    let mut hops = vec![];
+    let mut anchors = vec![];
+    let max_rise = jump_height() * SAFETY;
+
+    for segment in 0..difficulty.segments {
+        let prev = platforms[platforms.len() - 1].clone();
+        let prev_top = prev.max().y;
+        let width = rng.range(4., 2. + 2. * (1. - difficulty.platform));
+        let length = rng.range(4., 2. + 2. * (1. - difficulty.platform));
+        // stay overlapping in x with the last platform, so gaps are straight along z
+        let center_x = prev.origin.x + prev.dims.x / 2. + rng.range(-1., 1.) * (prev.dims.x.min(width) / 2. - 0.5);
+        let material = 1 + segment % 6;
+
+        let hop = if rng.float() < difficulty.grapple { Hop::Grapple(anchors.len()) } else { Hop::Jump };
+        let (rise, gap) = match hop {
+            Hop::Jump => {
+                let rise = rng.range(-1., 1.) * max_rise * difficulty.climb.max(0.1);
+                let reach = jump_reach(rise.max(0.)).unwrap_or(0.) * SAFETY;
+                (rise, rng.range(0.5, 0.5 + (reach - 0.5) * difficulty.gap.max(0.1)))
+            },
+            Hop::Grapple(_) => {
+                let reach = jump_reach(0.).unwrap_or(0.);
+                (rng.range(-1., 1.) * max_rise, rng.range(reach, reach * (1. + 1.5 * difficulty.gap)))
+            },
+        };
+
+        let top = prev_top + rise;
+        let origin = Vec3::new(center_x - width / 2., top - PLATFORM_THICKNESS, prev.max().z + gap);
+        let platform = Block::new(origin, Vec3::new(width, PLATFORM_THICKNESS, length), material);
+
+        if let Hop::Grapple(_) = hop {
+            // over the near edge of the landing, high enough to swing in under
+            let height = rng.range(PLAYER_HEIGHT + 1.5, PLAYER_HEIGHT + 4.);
+            let anchor_origin = Vec3::new(center_x - 0.5, top + height, origin.z);
+            anchors.push(Block::new(anchor_origin, Vec3::new(1., 1., 1.), 0));
+        }
+        platforms.push(platform);
+        hops.push(hop);
+    }
+
+    Course {
+        platforms,
+        hops,
+        anchors,
+    }
+}
+
+// Checks every hop independently of how it was built: against the player's
+// reach first, as that's quick, then by finding a way across with the real
+// physics.
+fn verify(course: &Course) -> bool {
+    let all_blocks: Vec<&Block> = course.platforms.iter().chain(course.anchors.iter()).collect();
+    for (index, hop) in course.hops.iter().enumerate() {
+        let from = &course.platforms[index];
+        let to = &course.platforms[index + 1];
+        let overlap_x = from.max().x.min(to.max().x) - from.min().x.max(to.min().x);
+        if overlap_x < 1. {
+            return false
+        }
+        let gap = to.min().z - from.max().z;
+        let rise = to.max().y - from.max().y;
+        match hop {
+            Hop::Jump => {
+                if rise > jump_height() * SAFETY {
+                    return false
+                }
+                match jump_reach(rise.max(0.)) {
+                    Some(reach) if gap <= reach * SAFETY => {},
+                    _ => return false,
+                }
+            },
+            Hop::Grapple(anchor) => {
+                let anchor = &course.anchors[*anchor];
+                // cast from standing at the far edge of the last platform
+                let eye = Vec3::new(anchor.origin.x + anchor.dims.x / 2., from.max().y + PLAYER_HEIGHT / 2., from.max().z);
+                let target = Vec3::new(anchor.origin.x + anchor.dims.x / 2., anchor.min().y, anchor.origin.z + anchor.dims.z / 2.);
+                let to_anchor = target - eye;
+                if to_anchor.length() > GRAPPLE_LENGTH * SAFETY {
+                    return false
+                }
+                // nothing in the way of the cast or the pull
+                let dir = to_anchor.unit();
+                for block in &all_blocks {
+                    if std::ptr::eq(*block, anchor) {
+                        continue
+                    }
+                    if let Some(distance) = block.ray_hit(&eye, &dir) {
+                        if distance > 0.01 && distance < to_anchor.length() {
+                            return false
+                        }
+                    }
+                }
+                // hanging from the anchor leaves room to drop onto the platform below
+                if anchor.min().y - to.max().y < PLAYER_HEIGHT + 1. {
+                    return false
+                }
+            },
+        }
+    }
+    (0..course.hops.len()).all(|index| crossable(course, index))
+}
+
+// Whether the solver finds a way from one platform onto the next, with just
+// the hop's own blocks around.
+fn crossable(course: &Course, index: usize) -> bool {
+    let from = &course.platforms[index];
+    let to = &course.platforms[index + 1];
+    let mut blocks = vec![from.clone(), to.clone()];
+    if let Hop::Grapple(anchor) = course.hops[index] {
+        blocks.push(course.anchors[anchor].clone());
+    }
+    let start_pos = Vec3::new(from.origin.x + from.dims.x / 2., from.max().y + PLAYER_HEIGHT / 2. + 0.5, from.origin.z + from.dims.z / 2.);
+    // shallow, so passing over the platform doesn't count as landing on it
+    let landing = Block::new(Vec3::new(to.origin.x, to.max().y, to.origin.z), Vec3::new(to.dims.x, 0.5, to.dims.z), 0);
+    let level = Level::new(Level::block_data_from(&blocks), landing, start_pos, Palette::classic(), Rules::non_contact(), vec![]);
+    solver::solve(&level).is_ok()
+}
+
+fn to_level(course: &Course) -> Level {
+    let first = &course.platforms[0];
+    let last = &course.platforms[course.platforms.len() - 1];
+    let start_pos = Vec3::new(first.origin.x + first.dims.x / 2., first.max().y + PLAYER_HEIGHT / 2. + 0.5, first.origin.z + 1.);
+    let win_block = Block::new(
+        Vec3::new(last.origin.x + last.dims.x / 2. - 1., last.max().y, last.max().z - 2.),
+        Vec3::new(2., 2., 1.5),
+        0,
+    );
+    let checkpoints = course.platforms.iter().enumerate()
+        .filter(|(index, _)| *index > 0 && index % CHECKPOINT_EVERY == 0 && *index < course.platforms.len() - 1)
+        .map(|(_, platform)| Block::new(Vec3::new(platform.origin.x, platform.max().y, platform.origin.z), Vec3::new(platform.dims.x, PLAYER_HEIGHT, platform.dims.z), 0))
+        .collect();
+    let blocks: Vec<Block> = course.platforms.iter().chain(course.anchors.iter()).cloned().collect();
+    Level::new(
+        Level::block_data_from(&blocks),
+        win_block,
+        start_pos,
+        Palette::classic(),
+        Rules::non_contact(),
+        checkpoints,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_level() {
+        let first = generate(20261019, Difficulty::normal()).map(|level| level.serialize());
+        let second = generate(20261019, Difficulty::normal()).map(|level| level.serialize());
+        assert!(first.is_some());
+        assert_eq!(first, second);
+    }
+
+    // Each hop was crossed on its own, so the whole course can be too.
+    #[test]
+    fn generated_levels_can_be_beaten() {
+        for seed in 0..3 {
+            match generate(seed, Difficulty::easy()) {
+                Some(level) => assert!(solver::solve(&level).is_ok(), "seed {} can't be beaten", seed),
+                None => panic!("seed {} generated nothing", seed),
+            }
+        }
+    }
+}
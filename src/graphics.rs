@@ -1,51 +1,338 @@
-use wasm_bindgen::prelude::*;
+use super::utils::Vec3;
 
-#[wasm_bindgen]
-#[derive(Clone)]
+// Keeps 16-bit indices clear of 0xFFFF, the primitive restart index in WebGL2.
+pub const MAX_BATCH_VERTICES: u32 = 65535;
+
+// Buffers are kept alive between frames and only cleared, so their
+// allocations are reused and JS can view them directly in wasm memory.
 pub struct Graphics {
-    positions: Vec<f32>,
-    colors: Vec<f32>,
+    pub positions: Vec<f32>,
+    pub colors: Vec<f32>,
+    pub normals: Vec<f32>,
     indices: Vec<u32>,
+    short_indices: Vec<u16>,
+
+    // flat list of (index start, index count, vertex start) per draw batch,
+    // indices are relative to the batch's vertex start
+    batches: Vec<u32>,
+    u32_indices: bool,
 
     cam_pos: Vec<f32>,
     cam_theta: f32,
     cam_phi: f32,
+
+    // per local player: viewport (x, y, width, height) as fractions of the
+    // canvas from the bottom left, and camera (x, y, z, theta, phi)
+    viewports: Vec<f32>,
+    cameras: Vec<f32>,
+
+    // view frustum, fov is vertical and in radians
+    fov: f32,
+    aspect: f32,
+    z_near: f32,
+    z_far: f32,
+    cam_forward: Vec3,
+    cam_right: Vec3,
+    cam_up: Vec3,
+
+    // fog fades geometry into the sky before it's culled at z_far
+    fog_start: f32,
+    fog_color: Vec<f32>,
+
+    sun_dir: Vec<f32>, // direction the light travels, unit length
+    ambient: f32,
 }
 
-#[wasm_bindgen]
 impl Graphics {
     pub fn new() -> Self {
         Self {
             positions: vec![],
             colors: vec![],
+            normals: vec![],
             indices: vec![],
-            cam_pos: vec![],
+            short_indices: vec![],
+            batches: vec![],
+            u32_indices: false,
+            cam_pos: vec![0.; 3],
             cam_theta: 0.,
             cam_phi: 0.,
+            viewports: vec![0., 0., 1., 1.],
+            cameras: vec![0.; 5],
+            fov: 45_f32.to_radians(),
+            aspect: 1.5,
+            z_near: 0.025,
+            z_far: 150.,
+            cam_forward: Vec3::new(0., 0., 1.),
+            cam_right: Vec3::new(1., 0., 0.),
+            cam_up: Vec3::new(0., 1., 0.),
+            fog_start: 90.,
+            fog_color: vec![0.012, 0.647, 0.988, 1.0],
+            sun_dir: vec![0., -1., 0.],
+            ambient: 1.,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.positions.clear();
+        self.colors.clear();
+        self.normals.clear();
+        self.indices.clear();
+        self.short_indices.clear();
+        self.batches.clear();
+    }
+
+    pub fn set_u32_indices(&mut self, supported: bool) {
+        self.u32_indices = supported;
+    }
+
+    // Adds the indices for a shape whose `vertex_count` vertices were just
+    // pushed, starting a new batch if they don't fit in the current one.
+    pub fn push_indices(&mut self, shape_indices: &[u32], vertex_count: u32) {
+        let shape_start = (self.positions.len() / 3) as u32 - vertex_count;
+        let batch_count = self.batches.len();
+
+        let fits = batch_count > 0 && (self.u32_indices || shape_start + vertex_count - self.batches[batch_count - 1] <= MAX_BATCH_VERTICES);
+        if !fits {
+            let index_start = if self.u32_indices { self.indices.len() } else { self.short_indices.len() };
+            self.batches.extend_from_slice(&[index_start as u32, 0, shape_start]);
         }
+
+        let batch_count = self.batches.len();
+        let base = shape_start - self.batches[batch_count - 1];
+        for index in shape_indices {
+            if self.u32_indices {
+                self.indices.push(base + index);
+            } else {
+                self.short_indices.push((base + index) as u16);
+            }
+        }
+        self.batches[batch_count - 2] += shape_indices.len() as u32;
     }
 
-    pub fn update(&mut self, positions: Vec<f32>, colors: Vec<f32>, indices: Vec<u32>, cam_pos: Vec<f32>, cam_theta: f32, cam_phi: f32) {
-        // update positions, colors, indices
-        self.positions = positions;
-        self.colors = colors;
-        self.indices = indices;
-        self.cam_pos = cam_pos;
+    pub fn set_camera(&mut self, cam_pos: Vec3, cam_theta: f32, cam_phi: f32) {
+        self.cam_pos[0] = cam_pos.x;
+        self.cam_pos[1] = cam_pos.y;
+        self.cam_pos[2] = cam_pos.z;
         self.cam_theta = cam_theta;
         self.cam_phi = cam_phi;
+
+        let (forward, right, up) = camera_basis(cam_theta, cam_phi);
+        self.cam_forward = forward;
+        self.cam_right = right;
+        self.cam_up = up;
+    }
+
+    // Where a point lands on screen in normalized device coordinates, and
+    // whether it's in front of the camera.
+    pub fn project(&self, point: &Vec3) -> (f32, f32, bool) {
+        let to_point = *point - Vec3::new(self.cam_pos[0], self.cam_pos[1], self.cam_pos[2]);
+        let z = to_point.dot(&self.cam_forward);
+        let x = to_point.dot(&self.cam_right);
+        let y = to_point.dot(&self.cam_up);
+        let half_v = (self.fov / 2.).tan();
+        let half_h = half_v * self.aspect;
+        if z <= 0. {
+            return (x / half_h, y / half_v, false)
+        }
+        (x / (z * half_h), y / (z * half_v), true)
+    }
+
+    // Splits the screen horizontally for two players, and into quarters for more.
+    pub fn set_views(&mut self, views: usize) {
+        self.viewports = match views {
+            1 => vec![0., 0., 1., 1.],
+            2 => vec![
+                0., 0.5, 1., 0.5,
+                0., 0., 1., 0.5,
+            ],
+            _ => vec![
+                0., 0.5, 0.5, 0.5,
+                0.5, 0.5, 0.5, 0.5,
+                0., 0., 0.5, 0.5,
+                0.5, 0., 0.5, 0.5,
+            ],
+        };
+        self.viewports.truncate(views * 4);
+        self.cameras = vec![0.; views * 5];
+    }
+
+    pub fn set_view_camera(&mut self, view: usize, cam_pos: Vec3, cam_theta: f32, cam_phi: f32) {
+        self.cameras[view * 5..view * 5 + 5].copy_from_slice(&[cam_pos.x, cam_pos.y, cam_pos.z, cam_theta, cam_phi]);
+    }
+
+    pub fn set_aspect(&mut self, aspect: f32) {
+        self.aspect = aspect;
+    }
+
+    // Vertical, in radians.
+    pub fn set_fov(&mut self, fov: f32) {
+        self.fov = fov;
     }
 
-    pub fn positions(&self) -> Vec<f32> {
-        self.positions.clone()
+    pub fn set_draw_distance(&mut self, z_far: f32, fog_start: f32) {
+        self.z_far = z_far;
+        self.fog_start = fog_start;
     }
-    pub fn colors(&self) -> Vec<f32> {
-        self.colors.clone()
+
+    // Conservative test of an AABB's bounding sphere against the view frustum.
+    pub fn in_view(&self, min: &Vec3, max: &Vec3) -> bool {
+        let center = (*min + *max) / 2.;
+        let radius = (*max - *min).length() / 2.;
+        let to_center = center - Vec3::new(self.cam_pos[0], self.cam_pos[1], self.cam_pos[2]);
+
+        let z = to_center.dot(&self.cam_forward);
+        if z + radius < self.z_near || z - radius > self.z_far {
+            return false
+        }
+
+        let half_v = self.fov / 2.;
+        let half_h = (half_v.tan() * self.aspect).atan();
+        let x = to_center.dot(&self.cam_right).abs();
+        let y = to_center.dot(&self.cam_up).abs();
+        let outside_h = x * half_h.cos() - z * half_h.sin() > radius;
+        let outside_v = y * half_v.cos() - z * half_v.sin() > radius;
+        !outside_h && !outside_v
+    }
+
+    // Skips blocks outside the view, so set the camera first.
+    pub fn push_block(&mut self, origin: &Vec3, dims: &Vec3, color: &[f32]) {
+        if !self.in_view(origin, &(*origin + *dims)) {
+            return
+        }
+        self.push_block_vertices(origin, dims);
+        self.push_block_normals();
+        for _face in 0..6 {
+            self.push_face_color(color);
+        }
+        self.push_indices(&Self::block_indices(), 24);
+    }
+
+    fn push_block_vertices(&mut self, origin: &Vec3, dims: &Vec3) {
+        let vertices = [
+            *origin,
+            *origin + Vec3::new(dims.x, 0., 0.),
+            *origin + Vec3::new(dims.x, dims.y, 0.),
+            *origin + Vec3::new(0., dims.y, 0.),
+
+            *origin,
+            *origin + Vec3::new(dims.x, 0., 0.),
+            *origin + Vec3::new(dims.x, 0., dims.z),
+            *origin + Vec3::new(0., 0., dims.z),
+
+            *origin,
+            *origin + Vec3::new(0., 0., dims.z),
+            *origin + Vec3::new(0., dims.y, dims.z),
+            *origin + Vec3::new(0., dims.y, 0.),
+
+            *origin + *dims,
+            *origin + *dims - Vec3::new(dims.x, 0., 0.),
+            *origin + *dims - Vec3::new(dims.x, dims.y, 0.),
+            *origin + *dims - Vec3::new(0., dims.y, 0.),
+
+            *origin + *dims,
+            *origin + *dims - Vec3::new(dims.x, 0., 0.),
+            *origin + *dims - Vec3::new(dims.x, 0., dims.z),
+            *origin + *dims - Vec3::new(0., 0., dims.z),
+
+            *origin + *dims,
+            *origin + *dims - Vec3::new(0., 0., dims.z),
+            *origin + *dims - Vec3::new(0., dims.y, dims.z),
+            *origin + *dims - Vec3::new(0., dims.y, 0.),
+        ];
+        for vertex in &vertices {
+            self.positions.extend_from_slice(&[vertex.x, vertex.y, vertex.z]);
+        }
+    }
+
+    // matches the face order of push_block_vertices
+    fn push_block_normals(&mut self) {
+        let face_normals = [
+            [0., 0., -1.],
+            [0., -1., 0.],
+            [-1., 0., 0.],
+            [0., 0., 1.],
+            [0., 1., 0.],
+            [1., 0., 0.],
+        ];
+        for normal in &face_normals {
+            for _vertex in 0..4 {
+                self.normals.extend_from_slice(normal);
+            }
+        }
+    }
+
+    fn block_indices() -> [u32; 36] {
+        let mut indices = [0; 36];
+        for face in 0..6 {
+            for (i, new_index) in [0, 1, 2, 0, 2, 3].iter().enumerate() {
+                indices[face * 6 + i] = new_index + face as u32 * 4;
+            }
+        }
+        indices
+    }
+
+    // colors are per vertex, so a face's color is repeated for its 4 vertices
+    pub fn push_face_color(&mut self, color: &[f32]) {
+        for _vertex in 0..4 {
+            self.colors.extend_from_slice(color);
+        }
     }
-    pub fn indices(&self) -> Vec<u32> {
-        self.indices.clone()
+
+    pub fn set_light(&mut self, sun_dir: Vec3, ambient: f32) {
+        let sun_dir = sun_dir.unit();
+        self.sun_dir[0] = sun_dir.x;
+        self.sun_dir[1] = sun_dir.y;
+        self.sun_dir[2] = sun_dir.z;
+        self.ambient = ambient;
+    }
+
+    pub fn positions_ptr(&self) -> *const f32 {
+        self.positions.as_ptr()
     }
-    pub fn cam_pos(&self) -> Vec<f32> {
-        self.cam_pos.clone()
+    pub fn positions_len(&self) -> usize {
+        self.positions.len()
+    }
+    pub fn colors_ptr(&self) -> *const f32 {
+        self.colors.as_ptr()
+    }
+    pub fn colors_len(&self) -> usize {
+        self.colors.len()
+    }
+    pub fn normals_ptr(&self) -> *const f32 {
+        self.normals.as_ptr()
+    }
+    pub fn normals_len(&self) -> usize {
+        self.normals.len()
+    }
+    pub fn indices_ptr(&self) -> *const u32 {
+        self.indices.as_ptr()
+    }
+    pub fn indices_len(&self) -> usize {
+        self.indices.len()
+    }
+    pub fn short_indices_ptr(&self) -> *const u16 {
+        self.short_indices.as_ptr()
+    }
+    pub fn short_indices_len(&self) -> usize {
+        self.short_indices.len()
+    }
+    pub fn batches_ptr(&self) -> *const u32 {
+        self.batches.as_ptr()
+    }
+    pub fn batches_len(&self) -> usize {
+        self.batches.len()
+    }
+    pub fn u32_indices(&self) -> bool {
+        self.u32_indices
+    }
+    pub fn viewports_ptr(&self) -> *const f32 {
+        self.viewports.as_ptr()
+    }
+    pub fn cameras_ptr(&self) -> *const f32 {
+        self.cameras.as_ptr()
+    }
+    pub fn cam_pos_ptr(&self) -> *const f32 {
+        self.cam_pos.as_ptr()
     }
     pub fn cam_theta(&self) -> f32 {
         self.cam_theta
@@ -53,4 +340,160 @@ impl Graphics {
     pub fn cam_phi(&self) -> f32 {
         self.cam_phi
     }
-}
\ No newline at end of file
+    pub fn fov(&self) -> f32 {
+        self.fov
+    }
+    pub fn z_near(&self) -> f32 {
+        self.z_near
+    }
+    pub fn z_far(&self) -> f32 {
+        self.z_far
+    }
+    pub fn fog_start(&self) -> f32 {
+        self.fog_start
+    }
+    pub fn fog_color_ptr(&self) -> *const f32 {
+        self.fog_color.as_ptr()
+    }
+    pub fn sun_dir_ptr(&self) -> *const f32 {
+        self.sun_dir.as_ptr()
+    }
+    pub fn ambient(&self) -> f32 {
+        self.ambient
+    }
+}
+
+// Forward, right and up for a camera. cam_phi is flipped for the view matrix,
+// the look direction uses the player's phi.
+pub fn camera_basis(cam_theta: f32, cam_phi: f32) -> (Vec3, Vec3, Vec3) {
+    let phi = -cam_phi;
+    let forward = Vec3::new(cam_theta.sin() * phi.cos(), phi.sin(), cam_theta.cos() * phi.cos());
+    let right = Vec3::new(cam_theta.cos(), 0., -cam_theta.sin());
+    let up = forward.cross(&right);
+    (forward, right, up)
+}
+
+// What JS reads to draw, for a wasm type that draws through a `graphics`
+// field. wasm_bindgen can't hand JS a borrowed Graphics, so each such type
+// passes these through.
+macro_rules! graphics_accessors {
+    ($owner:ty) => {
+        #[wasm_bindgen::prelude::wasm_bindgen]
+        impl $owner {
+            pub fn positions_ptr(&self) -> *const f32 {
+                self.graphics.positions_ptr()
+            }
+
+            pub fn positions_len(&self) -> usize {
+                self.graphics.positions_len()
+            }
+
+            pub fn colors_ptr(&self) -> *const f32 {
+                self.graphics.colors_ptr()
+            }
+
+            pub fn colors_len(&self) -> usize {
+                self.graphics.colors_len()
+            }
+
+            pub fn normals_ptr(&self) -> *const f32 {
+                self.graphics.normals_ptr()
+            }
+
+            pub fn normals_len(&self) -> usize {
+                self.graphics.normals_len()
+            }
+
+            pub fn indices_ptr(&self) -> *const u32 {
+                self.graphics.indices_ptr()
+            }
+
+            pub fn indices_len(&self) -> usize {
+                self.graphics.indices_len()
+            }
+
+            pub fn short_indices_ptr(&self) -> *const u16 {
+                self.graphics.short_indices_ptr()
+            }
+
+            pub fn short_indices_len(&self) -> usize {
+                self.graphics.short_indices_len()
+            }
+
+            pub fn batches_ptr(&self) -> *const u32 {
+                self.graphics.batches_ptr()
+            }
+
+            pub fn batches_len(&self) -> usize {
+                self.graphics.batches_len()
+            }
+
+            // Call with whether the renderer supports OES_element_index_uint.
+            pub fn set_u32_indices(&mut self, supported: bool) {
+                self.graphics.set_u32_indices(supported);
+            }
+
+            pub fn u32_indices(&self) -> bool {
+                self.graphics.u32_indices()
+            }
+
+            pub fn viewports_ptr(&self) -> *const f32 {
+                self.graphics.viewports_ptr()
+            }
+
+            pub fn cameras_ptr(&self) -> *const f32 {
+                self.graphics.cameras_ptr()
+            }
+
+            pub fn cam_pos_ptr(&self) -> *const f32 {
+                self.graphics.cam_pos_ptr()
+            }
+
+            pub fn cam_theta(&self) -> f32 {
+                self.graphics.cam_theta()
+            }
+
+            pub fn cam_phi(&self) -> f32 {
+                self.graphics.cam_phi()
+            }
+
+            pub fn set_aspect(&mut self, aspect: f32) {
+                self.graphics.set_aspect(aspect);
+            }
+
+            pub fn set_draw_distance(&mut self, z_far: f32, fog_start: f32) {
+                self.graphics.set_draw_distance(z_far, fog_start);
+            }
+
+            pub fn fov(&self) -> f32 {
+                self.graphics.fov()
+            }
+
+            pub fn z_near(&self) -> f32 {
+                self.graphics.z_near()
+            }
+
+            pub fn z_far(&self) -> f32 {
+                self.graphics.z_far()
+            }
+
+            pub fn fog_start(&self) -> f32 {
+                self.graphics.fog_start()
+            }
+
+            pub fn fog_color_ptr(&self) -> *const f32 {
+                self.graphics.fog_color_ptr()
+            }
+
+            pub fn sun_dir_ptr(&self) -> *const f32 {
+                self.graphics.sun_dir_ptr()
+            }
+
+            pub fn ambient(&self) -> f32 {
+                self.graphics.ambient()
+            }
+        }
+    };
+}
+
+pub(crate) use graphics_accessors;
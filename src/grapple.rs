@@ -1,14 +1,50 @@
+use wasm_bindgen::prelude::*;
 use super::utils::Vec3;
 use super::block::Block;
 use super::utils::AABB;
 use super::log;
+use super::snapshot::{StateWriter, StateReader};
 
+pub const GRAPPLE_LENGTH: f32 = 20.;
+pub const HANDS: usize = 2;
+pub const ASSIST_ANGLE: f32 = 0.06; // radians from the crosshair
+const ASSIST_RINGS: usize = 3;
+const ASSIST_SAMPLES: usize = 12; // rays per ring
+const PIVOT_OFFSET: f32 = 0.01; // keeps pivots just off the corner they wrap
+const MAX_PIVOTS: usize = 16;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq)]
+pub enum Hand {
+    Left = 0,
+    Right = 1,
+}
+
+impl Hand {
+    pub fn all() -> [Hand; HANDS] {
+        [Hand::Left, Hand::Right]
+    }
+}
+
+// A point the rope bends around. `bend` is the side the rope bent toward
+// when it wrapped, so it can unwrap once the player swings back past it.
+#[derive(Clone)]
+pub struct Pivot {
+    pub point: Vec3,
+    bend: Vec3,
+}
+
+#[derive(Clone)]
 pub struct Grapple {
     pub end: Vec3,
     cast_vel: Vec3,
 
     pub hooked: bool,
+    pub target: Option<usize>, // player hooked onto, if any
+    pub pulls_target: bool,
     pub length: f32,
+    pub pivots: Vec<Pivot>, // from the hook toward the player
+    pub rope_length: f32, // through every pivot, fixed once hooked
     
     pub pull: f32, // magnitude of force
 }
@@ -20,13 +56,158 @@ impl Grapple {
             cast_vel: (end - start).unit() * 0.4,
 
             hooked: false,
-            length: 20.,
+            target: None,
+            pulls_target: false,
+            length: GRAPPLE_LENGTH,
+            pivots: vec![],
+            rope_length: 0.,
             
             pull: 0.05,
         }
     }
 
-    pub fn cast(&mut self, blocks: &Vec<Block>, time_step: f32) {
+    pub fn write_state(&self, writer: &mut StateWriter) {
+        writer.vec3(&self.end);
+        writer.vec3(&self.cast_vel);
+        writer.bool(self.hooked);
+        // players are counted from 1 so 0 can mean no target
+        writer.usize(self.target.map_or(0, |target| target + 1));
+        writer.bool(self.pulls_target);
+        writer.f32(self.length);
+        writer.usize(self.pivots.len());
+        for pivot in &self.pivots {
+            writer.vec3(&pivot.point);
+            writer.vec3(&pivot.bend);
+        }
+        writer.f32(self.rope_length);
+        writer.f32(self.pull);
+    }
+
+    pub fn read_state(reader: &mut StateReader) -> Option<Self> {
+        Some(Self {
+            end: reader.vec3()?,
+            cast_vel: reader.vec3()?,
+            hooked: reader.bool()?,
+            target: reader.usize()?.checked_sub(1),
+            pulls_target: reader.bool()?,
+            length: reader.f32()?,
+            pivots: {
+                let mut pivots = vec![];
+                for _pivot in 0..reader.usize()? {
+                    pivots.push(Pivot { point: reader.vec3()?, bend: reader.vec3()? });
+                }
+                pivots
+            },
+            rope_length: reader.f32()?,
+            pull: reader.f32()?,
+        })
+    }
+
+    // The point the player swings around.
+    pub fn anchor(&self) -> Vec3 {
+        self.pivots.last().map_or(self.end, |pivot| pivot.point)
+    }
+
+    // Rope from the hook to the last pivot, which the player can't change.
+    pub fn fixed_length(&self) -> f32 {
+        let mut length = 0.;
+        let mut from = self.end;
+        for pivot in &self.pivots {
+            length += (pivot.point - from).length();
+            from = pivot.point;
+        }
+        length
+    }
+
+    // Rope left between the anchor and the player.
+    pub fn free_length(&self) -> f32 {
+        self.rope_length - self.fixed_length()
+    }
+
+    pub fn hook(&mut self, position: &Vec3) {
+        self.hooked = true;
+        self.rope_length = (self.end - *position).length();
+    }
+
+    // Unwraps pivots the player has swung back past, then wraps around
+    // anything the last segment now passes through.
+    pub fn wrap(&mut self, blocks: &[Block], prev_position: &Vec3, position: &Vec3) {
+        while let Some(pivot) = self.pivots.last() {
+            let before = if self.pivots.len() > 1 { self.pivots[self.pivots.len() - 2].point } else { self.end };
+            let bend = (pivot.point - before).cross(&(*position - pivot.point));
+            if bend.dot(&pivot.bend) >= 0. {
+                break
+            }
+            self.pivots.pop();
+        }
+
+        while self.pivots.len() < MAX_PIVOTS {
+            let anchor = self.anchor();
+            let block = match blocks.iter().find(|block| Self::blocks_segment(block, &anchor, position)) {
+                Some(block) => block,
+                None => break,
+            };
+            // the segment to prev_position was clear, so find where it first touches
+            let mut clear = *prev_position;
+            let mut blocked = *position;
+            if Self::blocks_segment(block, &anchor, &clear) {
+                break
+            }
+            for _step in 0..12 {
+                let mid = (clear + blocked) / 2.;
+                if Self::blocks_segment(block, &anchor, &mid) {
+                    blocked = mid;
+                } else {
+                    clear = mid;
+                }
+            }
+            let dir = (blocked - anchor).unit();
+            let touch = match block.ray_hit(&anchor, &dir) {
+                Some(distance) => anchor + dir * distance,
+                None => break,
+            };
+            let point = Self::off_edge(block, touch);
+            let bend = (point - anchor).cross(&(*position - point));
+            self.pivots.push(Pivot { point, bend });
+        }
+    }
+
+    fn blocks_segment(block: &Block, from: &Vec3, to: &Vec3) -> bool {
+        let length = (*to - *from).length();
+        if length <= PIVOT_OFFSET * 2. {
+            return false
+        }
+        match block.ray_hit(from, &((*to - *from) / length)) {
+            Some(distance) => distance > PIVOT_OFFSET && distance < length - PIVOT_OFFSET,
+            None => false,
+        }
+    }
+
+    // A rope only ever touches a box along an edge, so this moves where it
+    // touched onto the nearest edge, then just outside it.
+    fn off_edge(block: &Block, point: Vec3) -> Vec3 {
+        let min = block.min();
+        let max = block.max();
+        let mut coords = [point.x, point.y, point.z];
+        let bounds = [(min.x, max.x), (min.y, max.y), (min.z, max.z)];
+        let gap = |axis: usize| {
+            let (lo, hi) = bounds[axis];
+            (coords[axis] - lo).abs().min((coords[axis] - hi).abs())
+        };
+        let mut axes = [0, 1, 2];
+        axes.sort_by(|a, b| gap(*a).partial_cmp(&gap(*b)).unwrap_or(std::cmp::Ordering::Equal));
+        for axis in &axes[..2] {
+            let (lo, hi) = bounds[*axis];
+            coords[*axis] = if (coords[*axis] - lo).abs() < (coords[*axis] - hi).abs() {
+                lo - PIVOT_OFFSET
+            } else {
+                hi + PIVOT_OFFSET
+            };
+        }
+        Vec3::new(coords[0], coords[1], coords[2])
+    }
+
+    pub fn cast(&mut self, blocks: &Vec<Block>, position: &Vec3, time_step: f32) {
         self.end = self.end + self.cast_vel * time_step;
         for block in blocks {
             let min = block.min();
@@ -36,9 +217,48 @@ impl Grapple {
             let z_collide = self.end.z >= min.z && self.end.z <= max.z;
             let collide = x_collide && y_collide && z_collide;
             if collide {
-                self.hooked = true;
+                self.hook(position);
                 log("Hooked!");
             }
         }
     }
-}
\ No newline at end of file
+}
+
+// Where a grapple cast along a ray would hook, if it's within range.
+pub fn aim(blocks: &[Block], origin: &Vec3, dir: &Vec3) -> Option<Vec3> {
+    blocks.iter()
+        .filter_map(|block| block.ray_hit(origin, dir))
+        .filter(|distance| *distance <= GRAPPLE_LENGTH)
+        .fold(None, |nearest: Option<f32>, distance| Some(nearest.map_or(distance, |nearest| nearest.min(distance))))
+        .map(|distance| *origin + *dir * distance)
+}
+
+// When the crosshair misses, tries rays in widening rings around it and
+// returns the nearest direction that hooks. Misses near a block land just
+// past its edge, so this snaps to the closest edge.
+pub fn assist(blocks: &[Block], origin: &Vec3, dir: &Vec3) -> Option<Vec3> {
+    if aim(blocks, origin, dir).is_some() {
+        return Some(*dir)
+    }
+    let world_up = if dir.y.abs() < 0.99 { Vec3::new(0., 1., 0.) } else { Vec3::new(1., 0., 0.) };
+    let right = dir.cross(&world_up).unit();
+    let up = right.cross(dir).unit();
+    for ring in 1..=ASSIST_RINGS {
+        let angle = ASSIST_ANGLE * ring as f32 / ASSIST_RINGS as f32;
+        let mut best: Option<(f32, Vec3)> = None;
+        for sample in 0..ASSIST_SAMPLES {
+            let around = std::f32::consts::PI * 2. * sample as f32 / ASSIST_SAMPLES as f32;
+            let sample_dir = (*dir * angle.cos() + (right * around.cos() + up * around.sin()) * angle.sin()).unit();
+            if let Some(point) = aim(blocks, origin, &sample_dir) {
+                let distance = (point - *origin).length();
+                if best.is_none_or(|(best_distance, _)| distance < best_distance) {
+                    best = Some((distance, sample_dir));
+                }
+            }
+        }
+        if let Some((_, sample_dir)) = best {
+            return Some(sample_dir)
+        }
+    }
+    None
+}
@@ -0,0 +1,44 @@
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq)]
+pub enum GrappleStatus {
+    Ready,
+    Casting,
+    Hooked,
+    HookedPlayer,
+}
+
+// What a player needs on screen, read once per frame after update.
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct Hud {
+    pub speed: f32, // units per second
+    pub left_grapple: GrappleStatus,
+    pub left_grapple_distance: f32, // 0 when there's no grapple
+    pub right_grapple: GrappleStatus,
+    pub right_grapple_distance: f32,
+    pub grapple_length: f32,
+    pub time: f32, // ms
+    pub countdown: f32, // ms until the run starts or carries on, 0 when it's running
+    pub checkpoint: Option<usize>,
+    pub checkpoints: usize,
+    // where the win block is on screen in normalized device coordinates, or
+    // a unit direction toward it from the center when it's off screen
+    pub goal_x: f32,
+    pub goal_y: f32,
+    pub goal_on_screen: bool,
+    pub goal_distance: f32,
+}
+
+// Where the crosshair would hook right now.
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct Aim {
+    pub hit: bool,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub distance: f32,
+    pub assisted: bool, // the hit came from aim assist, not the crosshair itself
+}
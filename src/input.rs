@@ -0,0 +1,148 @@
+use wasm_bindgen::prelude::*;
+use super::snapshot::{StateWriter, StateReader};
+
+// how far a stick can drift from center and still count as centered
+pub const STICK_DEADZONE: f32 = 0.2;
+
+// radians per tick with the look stick all the way over
+const LOOK_STICK_SPEED: f32 = 0.025;
+// held all the way over, turning speeds up by this much more over this many ticks
+const LOOK_BOOST: f32 = 1.;
+const LOOK_BOOST_TICKS: f32 = 30.;
+const LOOK_FULL: f32 = 0.95; // how far over counts as all the way
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Stick {
+    Move = 0, // x right, y forward
+    Look = 1, // x right, y down
+}
+
+impl Stick {
+    pub fn from_u8(code: u8) -> Option<Stick> {
+        match code {
+            0 => Some(Stick::Move),
+            1 => Some(Stick::Look),
+            _ => None,
+        }
+    }
+}
+
+// A stick position with the deadzone taken out: nothing inside it, then
+// rising from 0 at its edge to 1 at the rim, in the same direction.
+pub fn deadzone(x: f32, y: f32) -> (f32, f32) {
+    let length = (x * x + y * y).sqrt();
+    if length <= STICK_DEADZONE {
+        return (0., 0.)
+    }
+    let scaled = ((length - STICK_DEADZONE) / (1. - STICK_DEADZONE)).min(1.);
+    (x / length * scaled, y / length * scaled)
+}
+
+// Everything a player is holding, digital and analog alike. Buttons count as
+// sticks pushed all the way, and both add up, so a keyboard and a gamepad can
+// drive the same player.
+#[derive(Clone)]
+pub struct InputState {
+    moving: [bool; 4], // left, forward, right, back
+    looking: [bool; 4], // left, right, up, down
+    sticks: [(f32, f32); 2], // indexed by Stick, deadzone already taken out
+    look_held_for: f32, // ticks the look has been all the way over
+}
+
+impl InputState {
+    pub fn new() -> Self {
+        Self {
+            moving: [false; 4],
+            looking: [false; 4],
+            sticks: [(0., 0.); 2],
+            look_held_for: 0.,
+        }
+    }
+
+    // Indexed like Go: left, forward, right, back.
+    pub fn set_moving(&mut self, direction: usize, held: bool) {
+        self.moving[direction] = held;
+    }
+
+    // Left, right, up, down.
+    pub fn set_looking(&mut self, direction: usize, held: bool) {
+        self.looking[direction] = held;
+    }
+
+    pub fn stick(&self, stick: Stick) -> (f32, f32) {
+        self.sticks[stick as usize]
+    }
+
+    pub fn set_stick(&mut self, stick: Stick, value: (f32, f32)) {
+        self.sticks[stick as usize] = value;
+    }
+
+    // Which way to run, strafing right and forward. It's longer than 1 when
+    // buttons and the stick add up; anything from 1 up is full speed.
+    pub fn movement(&self) -> (f32, f32) {
+        let held = |direction: usize| if self.moving[direction] { 1. } else { 0. };
+        let (x, y) = self.stick(Stick::Move);
+        (x + held(2) - held(0), y + held(1) - held(3))
+    }
+
+    // How far to turn this tick, in radians: right, then down, each scaled by
+    // the player's look sensitivity. Light pushes are squared down for fine
+    // aim, and holding all the way over speeds up the longer it's held.
+    pub fn look(&mut self, time_step: f32, scale: (f32, f32)) -> (f32, f32) {
+        let held = |direction: usize| if self.looking[direction] { 1. } else { 0. };
+        let (x, y) = self.stick(Stick::Look);
+        let (x, y) = clamp_length(x + held(1) - held(0), y + held(3) - held(2));
+        let amount = (x * x + y * y).sqrt();
+        if amount >= LOOK_FULL {
+            self.look_held_for += time_step;
+        } else {
+            self.look_held_for = 0.;
+        }
+        if amount <= 0. {
+            return (0., 0.)
+        }
+        let boost = 1. + LOOK_BOOST * (self.look_held_for / LOOK_BOOST_TICKS).min(1.);
+        let speed = LOOK_STICK_SPEED * amount * amount * boost * time_step;
+        (x / amount * speed * scale.0, y / amount * speed * scale.1)
+    }
+
+    // Everything held, as numbers for checksums.
+    pub fn values(&self) -> Vec<f32> {
+        let held = self.moving.iter().chain(self.looking.iter()).map(|held| if *held { 1. } else { 0. });
+        let sticks = self.sticks.iter().flat_map(|(x, y)| vec![*x, *y]);
+        held.chain(sticks).chain(std::iter::once(self.look_held_for)).collect()
+    }
+
+    pub fn write_state(&self, writer: &mut StateWriter) {
+        for held in self.moving.iter().chain(self.looking.iter()) {
+            writer.bool(*held);
+        }
+        for (x, y) in self.sticks.iter() {
+            writer.f32(*x);
+            writer.f32(*y);
+        }
+        writer.f32(self.look_held_for);
+    }
+
+    pub fn read_state(reader: &mut StateReader) -> Option<Self> {
+        let mut state = Self::new();
+        for held in state.moving.iter_mut().chain(state.looking.iter_mut()) {
+            *held = reader.bool()?;
+        }
+        for stick in state.sticks.iter_mut() {
+            *stick = (reader.f32()?, reader.f32()?);
+        }
+        state.look_held_for = reader.f32()?;
+        Some(state)
+    }
+}
+
+fn clamp_length(x: f32, y: f32) -> (f32, f32) {
+    let length = (x * x + y * y).sqrt();
+    if length > 1. {
+        (x / length, y / length)
+    } else {
+        (x, y)
+    }
+}
@@ -1,25 +1,112 @@
 use super::block::Block;
 use super::utils::Vec3;
+use super::palette::Palette;
+use super::rules::{Rules, PlayerGrapple};
+use super::snapshot::{StateWriter, StateReader};
+
+// Blocks are stored flat as 7 floats each:
+// origin x, y, z, dims x, y, z, material id
+pub const BLOCK_DATA_LEN: usize = 7;
+
+// bump when the serialized layout changes
+const LEVEL_VERSION: u32 = 1;
 
 pub struct Level {
     block_data: Vec<f32>,
     win_block: Block,
     start_pos: Vec3,
+    palette: Palette,
+    rules: Rules,
+    checkpoints: Vec<Block>, // trigger volumes, in course order
 }
 
 impl Level {
-    pub fn new(block_data: Vec<f32>, win_block: Block, start_pos: Vec3) -> Self {
+    pub fn new(block_data: Vec<f32>, win_block: Block, start_pos: Vec3, palette: Palette, rules: Rules, checkpoints: Vec<Block>) -> Self {
         Self {
             block_data,
             win_block,
-            start_pos
+            start_pos,
+            palette,
+            rules,
+            checkpoints,
+        }
+    }
+
+    // Whitespace separated values, led by "level <version>", in the same
+    // order as Level::new's arguments.
+    pub fn serialize(&self) -> String {
+        let mut writer = StateWriter::new();
+        writer.word("level");
+        writer.u32(LEVEL_VERSION);
+        writer.usize(self.block_data.len() / BLOCK_DATA_LEN);
+        for value in &self.block_data {
+            writer.f32(*value);
+        }
+        Self::write_block(&mut writer, &self.win_block);
+        writer.vec3(&self.start_pos);
+        writer.word(&self.palette.name);
+        writer.bool(self.rules.player_collision);
+        writer.u32(self.rules.player_grapple as u32);
+        writer.usize(self.checkpoints.len());
+        for checkpoint in &self.checkpoints {
+            Self::write_block(&mut writer, checkpoint);
         }
+        writer.finish()
+    }
+
+    pub fn deserialize(data: &str) -> Option<Level> {
+        let mut reader = StateReader::new(data);
+        if reader.word()? != "level" || reader.u32()? != LEVEL_VERSION {
+            return None
+        }
+        let mut block_data = vec![];
+        for _value in 0..reader.usize()?.checked_mul(BLOCK_DATA_LEN)? {
+            block_data.push(reader.f32()?);
+        }
+        let win_block = Self::read_block(&mut reader)?;
+        let start_pos = reader.vec3()?;
+        let palette = Palette::by_name(reader.word()?)?;
+        let rules = Rules {
+            player_collision: reader.bool()?,
+            player_grapple: PlayerGrapple::from_u8(reader.u32()? as u8)?,
+        };
+        let mut checkpoints = vec![];
+        for _checkpoint in 0..reader.usize()? {
+            checkpoints.push(Self::read_block(&mut reader)?);
+        }
+        Some(Level::new(block_data, win_block, start_pos, palette, rules, checkpoints))
+    }
+
+    fn write_block(writer: &mut StateWriter, block: &Block) {
+        writer.vec3(&block.origin);
+        writer.vec3(&block.dims);
+        writer.usize(block.material);
+    }
+
+    fn read_block(reader: &mut StateReader) -> Option<Block> {
+        Some(Block::new(reader.vec3()?, reader.vec3()?, reader.usize()?))
     }
 
     pub fn block_data(&self) -> Vec<f32> {
         self.block_data.clone()
     }
 
+    pub fn blocks(&self) -> Vec<Block> {
+        self.block_data.chunks_exact(BLOCK_DATA_LEN).map(|block| {
+            let origin = Vec3::new(block[0], block[1], block[2]);
+            let dims = Vec3::new(block[3], block[4], block[5]);
+            Block::new(origin, dims, block[6] as usize)
+        }).collect()
+    }
+
+    pub fn block_data_from(blocks: &[Block]) -> Vec<f32> {
+        blocks.iter().flat_map(|block| vec![
+            block.origin.x, block.origin.y, block.origin.z,
+            block.dims.x, block.dims.y, block.dims.z,
+            block.material as f32,
+        ]).collect()
+    }
+
     pub fn start_pos(&self) -> Vec3 {
         self.start_pos
     }
@@ -27,4 +114,26 @@ impl Level {
     pub fn win_block(&self) -> Block {
         self.win_block.clone()
     }
-}
\ No newline at end of file
+
+    pub fn palette(&self) -> Palette {
+        self.palette.clone()
+    }
+
+    pub fn rules(&self) -> Rules {
+        self.rules
+    }
+
+    pub fn checkpoints(&self) -> Vec<Block> {
+        self.checkpoints.clone()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_counts_too_big_to_read_are_rejected() {
+        let data = format!("level {} {}", LEVEL_VERSION, usize::MAX / 2);
+        assert!(Level::deserialize(&data).is_none());
+    }
+}
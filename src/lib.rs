@@ -1,22 +1,38 @@
 mod utils;
 
-mod master;
+pub mod master;
 use master::Master;
 
-mod level;
+pub mod level;
 
 mod player;
 
 mod universe;
 use universe::Universe;
 
+pub mod netcode;
+
 mod grapple;
 mod block;
+mod palette;
+mod rules;
+mod snapshot;
+mod events;
+mod hud;
+mod editor;
+mod generator;
+mod catalog;
+mod replay;
+mod settings;
+mod bindings;
+mod input;
+pub mod save;
+pub mod solver;
 mod graphics;
-use graphics::Graphics;
 
 use std::cmp::min;
 
+#[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
 extern crate js_sys;
@@ -27,6 +43,7 @@ extern crate js_sys;
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
+#[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
 extern "C" {
     // Use `js_namespace` here to bind `console.log(..)` instead of just
@@ -36,4 +53,8 @@ extern "C" {
 
     #[wasm_bindgen(js_namespace = console)]
     pub fn log(s: &str);
-}
\ No newline at end of file
+}
+
+// Native builds (tools, loopback netcode) have no console to log to.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn log(_s: &str) {}
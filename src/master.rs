@@ -1,75 +1,393 @@
 use wasm_bindgen::prelude::*;
 use super::Universe;
+use super::editor::Editor;
+use super::catalog::{Catalog, Pack, Entry, Medal, PackInfo, LevelInfo};
+use super::save::SaveData;
+use super::settings::{Settings, MIN_FOV, MAX_FOV};
+use super::bindings::{Action, Bindings, Bound, Control, Device};
+use super::generator::{self, Difficulty};
 use super::level::Level;
 use super::utils::Vec3;
 use super::block::Block;
+use super::palette::Palette;
+use super::rules::Rules;
 use super::log;
 
+pub const COUNTDOWN_TICKS: u32 = 180; // 3 seconds before every run
+
 #[wasm_bindgen]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Mode {
     Menu,
+    Countdown, // before go
     Play,
+    Paused,
     WonLevel,
+    Edit,
+}
+
+impl Mode {
+    // Whether the menus may go from one mode to the other. Staying in the
+    // same mode is always fine.
+    fn can_switch(from: Mode, to: Mode) -> bool {
+        if from == to {
+            return true
+        }
+        matches!((from, to),
+            (Mode::Menu, Mode::Countdown) | (Mode::Menu, Mode::Play) | (Mode::Menu, Mode::Edit)
+            | (Mode::Countdown, Mode::Play) | (Mode::Countdown, Mode::Paused) | (Mode::Countdown, Mode::Menu)
+            | (Mode::Play, Mode::Countdown) | (Mode::Play, Mode::Paused) | (Mode::Play, Mode::WonLevel) | (Mode::Play, Mode::Menu) | (Mode::Play, Mode::Edit)
+            | (Mode::Paused, Mode::Countdown) | (Mode::Paused, Mode::Play) | (Mode::Paused, Mode::Menu)
+            | (Mode::WonLevel, Mode::Countdown) | (Mode::WonLevel, Mode::Play) | (Mode::WonLevel, Mode::Menu) | (Mode::WonLevel, Mode::Edit)
+            | (Mode::Edit, Mode::Play) | (Mode::Edit, Mode::Menu)
+        )
+    }
 }
 
 #[wasm_bindgen]
 pub struct Master {
     levels: Vec<Level>,
+    catalog: Catalog,
+    save: SaveData,
     mode: Mode,
 }
 
+impl Master {
+    pub fn level(&self, level: usize) -> Option<&Level> {
+        self.levels.get(level)
+    }
+
+    // The level's best run, played back from its replay into a fresh start,
+    // for checking the record stands up. None if there's no replay of it.
+    pub fn replay_record(&self, level: usize) -> Option<Universe> {
+        let (pack, entry) = self.catalog.find(level)?;
+        let entry = self.catalog.entry(pack, entry)?;
+        let replay = self.save.record(&entry.id)?.replay.as_ref()?;
+        let mut universe = self.start(level, 1)?;
+        replay.play(&mut universe);
+        Some(universe)
+    }
+}
+
+impl Default for Master {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[wasm_bindgen]
 impl Master {
     pub fn new() -> Self {
         let level1_block_data = vec![
-            -10., -1., -10., 20., 1., 25.,
+            -10., -1., -10., 20., 1., 25., 0.,
 
-            -10., 0., 10., 20., 5., 2.,
-            -4., 0., 6., 5., 1.5, 4.,
-            -3.25, 1.5, 7., 3., 1.5, 3.,
-            -2.25, 3., 8., 1.5, 1., 2.,
+            -10., 0., 10., 20., 5., 2., 1.,
+            -4., 0., 6., 5., 1.5, 4., 2.,
+            -3.25, 1.5, 7., 3., 1.5, 3., 3.,
+            -2.25, 3., 8., 1.5, 1., 2., 4.,
 
-            -10., 2., 25., 20., 1., 25.,
-            -5., 11., 18., 10., 1., 8.,
+            -10., 2., 25., 20., 1., 25., 0.,
+            -5., 11., 18., 10., 1., 8., 5.,
 
-            -7., 4., 35., 3., 0.3, 3.,
-            -7., 5.5, 39.5, 3., 0.3, 3.,
-            -7., 9., 47., 3., 3., 0.3,
-            -7., 13., 49.5, 3., 0.3, 3.,
+            -7., 4., 35., 3., 0.3, 3., 6.,
+            -7., 5.5, 39.5, 3., 0.3, 3., 1.,
+            -7., 9., 47., 3., 3., 0.3, 2.,
+            -7., 13., 49.5, 3., 0.3, 3., 3.,
 
-            -1.5, 20., 55., 3., 0.3, 3.,
+            -1.5, 20., 55., 3., 0.3, 3., 4.,
 
-            4.5, 15., 60., 3., 0.3, 6.,
+            4.5, 15., 60., 3., 0.3, 6., 5.,
         ];
         let level2_block_data = vec![
-            -10., -1., -10., 20., 1., 25.,
+            -10., -1., -10., 20., 1., 25., 0.,
         ];
         Self {
             levels: vec![
                 Level::new(
                     level1_block_data,
-                    Block::new(Vec3::new(4.5, 15.3, 64.), Vec3::new(3., 3., 2.)),
+                    Block::new(Vec3::new(4.5, 15.3, 64.), Vec3::new(3., 3., 2.), 0),
                     Vec3::new(2., 1.5, -5.),
+                    Palette::classic(),
+                    Rules::non_contact(),
+                    vec![
+                        Block::new(Vec3::new(-10., 3., 25.), Vec3::new(20., 3., 2.), 0),
+                        Block::new(Vec3::new(-1.5, 20.3, 55.), Vec3::new(3., 2., 3.), 0),
+                    ],
                 ),
                 Level::new(
                     level2_block_data,
-                    Block::new(Vec3::new(0., 0., 0.), Vec3::new(0., 0., 0.)),
+                    Block::new(Vec3::new(-1.5, 0., 11.), Vec3::new(3., 3., 3.), 0),
                     Vec3::new(2., 1.5, -5.),
+                    Palette::dusk(),
+                    Rules::contact(),
+                    vec![],
                 ),
             ],
+            catalog: Catalog::new(vec![
+                Pack {
+                    id: "training".to_string(),
+                    name: "Training".to_string(),
+                    description: "Running, jumping and swinging.".to_string(),
+                    entries: vec![
+                        Entry {
+                            id: "training-climb".to_string(),
+                            name: "The Climb".to_string(),
+                            description: "Up the steps, across the ledges and swing to the top.".to_string(),
+                            level: 0,
+                            par_time: 8500.,
+                            medal_times: [17700., 12400., 8800.],
+                            unlock_medals: None,
+                        },
+                        Entry {
+                            id: "training-arena".to_string(),
+                            name: "Arena".to_string(),
+                            description: "An open floor for racing and grappling each other.".to_string(),
+                            level: 1,
+                            par_time: 2700.,
+                            medal_times: [5500., 3900., 2800.],
+                            unlock_medals: None,
+                        },
+                    ],
+                },
+            ]),
+            save: SaveData::new(),
             mode: Mode::Menu,
         }
     }
 
-    pub fn start(&self, level: usize) -> Universe {
-        let curr_level = &self.levels[level];
-        Universe::new(curr_level.block_data(), curr_level.win_block(), curr_level.start_pos())
+    // None if there's no such level, or it's still locked.
+    pub fn start(&self, level: usize, local_players: usize) -> Option<Universe> {
+        let curr_level = self.levels.get(level)?;
+        if let Some((pack, entry)) = self.catalog.find(level) {
+            if !self.catalog.unlocked(&self.save, pack, entry) {
+                return None
+            }
+        }
+        let mut universe = Universe::new(curr_level, local_players);
+        let settings = &self.save.settings;
+        universe.set_practice(settings.practice);
+        universe.set_aim_assist(settings.aim_assist);
+        // practice runs can't set records, so there's nothing to replay
+        universe.set_recording(!settings.practice);
+        universe.set_countdown(COUNTDOWN_TICKS);
+        universe.set_settings(settings);
+        Some(universe)
+    }
+
+    pub fn num_packs(&self) -> usize {
+        self.catalog.packs().len()
+    }
+
+    pub fn pack_info(&self, pack: usize) -> Option<PackInfo> {
+        self.catalog.pack_info(&self.save, pack)
+    }
+
+    pub fn level_info(&self, pack: usize, entry: usize) -> Option<LevelInfo> {
+        self.catalog.level_info(&self.save, pack, entry)
+    }
+
+    // Records a won run of a level, with its replay if it's the best yet,
+    // and returns the medal it earned. Finishing may unlock other levels.
+    pub fn finish(&mut self, level: usize, universe: &Universe) -> Medal {
+        if !universe.counts_for_records() {
+            return Medal::None
+        }
+        self.catalog.finish(&mut self.save, level, universe.run_time(), universe.replay().cloned())
+    }
+
+    // For JS to keep in localStorage, and pass back to load_save_data next time.
+    pub fn save_data(&self) -> String {
+        self.save.serialize()
+    }
+
+    // Returns false, keeping the current save data, if the data doesn't parse.
+    pub fn load_save_data(&mut self, data: &str) -> bool {
+        match SaveData::deserialize(data) {
+            Some(save) => {
+                self.save = save;
+                true
+            },
+            None => false,
+        }
+    }
+
+    // Levels past the end open a blank level.
+    pub fn edit(&self, level: usize) -> Editor {
+        match self.levels.get(level) {
+            Some(level) => Editor::new(level),
+            None => Editor::blank(),
+        }
+    }
+
+    // A run of the level being edited, starting from the editor's camera.
+    pub fn playtest(&self, editor: &Editor) -> Universe {
+        let mut universe = Universe::new(&editor.playtest_level(), 1);
+        universe.set_settings(&self.save.settings);
+        universe
+    }
+
+    // Adds a level exported from the editor, returning its index, or
+    // None if the data doesn't parse.
+    pub fn add_level(&mut self, data: &str) -> Option<usize> {
+        let level = Level::deserialize(data)?;
+        self.levels.push(level);
+        Some(self.levels.len() - 1)
+    }
+
+    // Adds a generated level and returns its index. The same seed and
+    // difficulty always give the same level. None if nothing generated
+    // could be shown to be beatable.
+    pub fn generate(&mut self, seed: u32, difficulty: Difficulty) -> Option<usize> {
+        self.levels.push(generator::generate(seed, difficulty)?);
+        Some(self.levels.len() - 1)
+    }
+
+    pub fn num_levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn set_practice(&mut self, practice: bool) {
+        self.save.settings.practice = practice;
+    }
+
+    pub fn practice(&self) -> bool {
+        self.save.settings.practice
+    }
+
+    pub fn set_aim_assist(&mut self, enabled: bool) {
+        self.save.settings.aim_assist = enabled;
+    }
+
+    pub fn aim_assist(&self) -> bool {
+        self.save.settings.aim_assist
+    }
+
+    pub fn set_local_players(&mut self, local_players: usize) {
+        self.save.settings.local_players = local_players;
+    }
+
+    pub fn local_players(&self) -> usize {
+        self.save.settings.local_players
+    }
+
+    // What a control does, for the page to pass on to Universe::action or
+    // handle itself. Gamepad bindings come back as player 0, for the page to
+    // swap for the player that gamepad drives.
+    pub fn lookup(&self, control: &Control) -> Bound {
+        self.save.bindings.lookup(control)
+    }
+
+    // Binds a control to a player's action, replacing the action's old control
+    // on that device. If the control is already bound to something else it's
+    // left alone and the conflict is returned, for the page to ask about and
+    // unbind first; otherwise the returned Bound isn't found.
+    pub fn bind(&mut self, player: usize, action: Action, control: &Control) -> Bound {
+        self.save.bindings.bind(player, action, control.clone())
+    }
+
+    pub fn unbind(&mut self, control: &Control) {
+        self.save.bindings.unbind(control);
+    }
+
+    // The name of what's bound to a player's action, or "" if nothing is.
+    pub fn binding_name(&self, player: usize, action: Action, device: Device) -> String {
+        self.save.bindings.control(player, action, device).map_or(String::new(), |control| control.name())
+    }
+
+    pub fn reset_bindings(&mut self) {
+        self.save.bindings = Bindings::defaults();
     }
 
-    pub fn set_mode(&mut self, new_mode: Mode) {
+    // The look and view settings below apply to runs started from now on,
+    // and to one already going once it's passed to apply_settings.
+
+    // Scales mouse look on both axes, on top of their own scales.
+    pub fn set_sensitivity(&mut self, sensitivity: f32) {
+        self.save.settings.sensitivity = sensitivity.max(0.);
+    }
+
+    pub fn sensitivity(&self) -> f32 {
+        self.save.settings.sensitivity
+    }
+
+    pub fn set_sensitivity_x(&mut self, scale: f32) {
+        self.save.settings.sensitivity_x = scale.max(0.);
+    }
+
+    pub fn sensitivity_x(&self) -> f32 {
+        self.save.settings.sensitivity_x
+    }
+
+    pub fn set_sensitivity_y(&mut self, scale: f32) {
+        self.save.settings.sensitivity_y = scale.max(0.);
+    }
+
+    pub fn sensitivity_y(&self) -> f32 {
+        self.save.settings.sensitivity_y
+    }
+
+    pub fn set_invert_y(&mut self, invert: bool) {
+        self.save.settings.invert_y = invert;
+    }
+
+    pub fn invert_y(&self) -> bool {
+        self.save.settings.invert_y
+    }
+
+    // Vertical, in degrees, kept between MIN_FOV and MAX_FOV.
+    pub fn set_fov(&mut self, fov: f32) {
+        self.save.settings.fov = if fov.is_nan() { Settings::new().fov } else { fov.clamp(MIN_FOV, MAX_FOV) };
+    }
+
+    pub fn fov(&self) -> f32 {
+        self.save.settings.fov
+    }
+
+    pub fn set_head_bob(&mut self, enabled: bool) {
+        self.save.settings.head_bob = enabled;
+    }
+
+    pub fn head_bob(&self) -> bool {
+        self.save.settings.head_bob
+    }
+
+    pub fn apply_settings(&self, universe: &mut Universe) {
+        universe.set_settings(&self.save.settings);
+    }
+
+    // Returns false, staying in the current mode, if the switch isn't allowed.
+    pub fn set_mode(&mut self, new_mode: Mode) -> bool {
+        if !Mode::can_switch(self.mode, new_mode) {
+            log(&format!("can't switch from {:?} to {:?}", self.mode, new_mode)[..]);
+            return false
+        }
         log(&format!("switched!")[..]);
         self.mode = new_mode;
+        true
+    }
+
+    pub fn pause(&mut self, universe: &mut Universe) -> bool {
+        if !self.set_mode(Mode::Paused) {
+            return false
+        }
+        universe.pause();
+        true
+    }
+
+    // Back to play, after the universe's resume countdown, or back to the
+    // start countdown if it was paused before go.
+    pub fn resume(&mut self, universe: &mut Universe) -> bool {
+        let mode = if universe.counting_down() { Mode::Countdown } else { Mode::Play };
+        if self.mode != Mode::Paused || !self.set_mode(mode) {
+            return false
+        }
+        universe.resume();
+        true
+    }
+
+    // Once the universe's countdown hits go. Returns whether it has.
+    pub fn update_countdown(&mut self, universe: &Universe) -> bool {
+        self.mode == Mode::Countdown && !universe.counting_down() && self.set_mode(Mode::Play)
     }
 
     pub fn mode(&self) -> Mode {
@@ -0,0 +1,613 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+use super::universe::{Universe, Input, TICK_TIME};
+use super::snapshot::Snapshot;
+use super::input::Stick;
+
+// how often (in ticks) peers exchange state checksums
+const CHECKSUM_INTERVAL: u32 = 30;
+// how far rollback may simulate ahead of the last tick with every input known
+const MAX_PREDICTION: u32 = 8;
+
+const PACKET_INPUT: u8 = 0;
+const PACKET_CHECKSUM: u8 = 1;
+
+// Moves packets between peers. Sends are broadcast to every other peer.
+pub trait Transport {
+    fn send(&mut self, packet: Vec<u8>);
+    fn receive(&mut self) -> Option<Vec<u8>>;
+}
+
+// In-process transport, for running clients side by side without a network.
+pub struct LoopbackTransport {
+    index: usize,
+    inboxes: Rc<RefCell<Vec<VecDeque<Vec<u8>>>>>,
+}
+
+impl LoopbackTransport {
+    pub fn network(peers: usize) -> Vec<LoopbackTransport> {
+        let inboxes = Rc::new(RefCell::new(vec![VecDeque::new(); peers]));
+        (0..peers).map(|index| LoopbackTransport { index, inboxes: inboxes.clone() }).collect()
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn send(&mut self, packet: Vec<u8>) {
+        for (index, inbox) in self.inboxes.borrow_mut().iter_mut().enumerate() {
+            if index != self.index {
+                inbox.push_back(packet.clone());
+            }
+        }
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        self.inboxes.borrow_mut()[self.index].pop_front()
+    }
+}
+
+// Buffers packets for the host to carry, e.g. over a WebSocket from JS.
+pub struct QueueTransport {
+    outgoing: VecDeque<Vec<u8>>,
+    incoming: VecDeque<Vec<u8>>,
+}
+
+impl QueueTransport {
+    pub fn new() -> Self {
+        Self {
+            outgoing: VecDeque::new(),
+            incoming: VecDeque::new(),
+        }
+    }
+}
+
+impl Default for QueueTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transport for QueueTransport {
+    fn send(&mut self, packet: Vec<u8>) {
+        self.outgoing.push_back(packet);
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        self.incoming.pop_front()
+    }
+}
+
+// Everything one player did during one tick.
+#[derive(Clone, PartialEq)]
+pub struct InputFrame {
+    pub tick: u32,
+    pub player: u8,
+    pub inputs: Vec<Input>,
+    pub sticks: Vec<(Stick, (f32, f32))>, // where they were moved to
+    pub look_scale: Option<(f32, f32)>, // if the look sensitivity changed
+    pub look: (f32, f32),
+}
+
+impl InputFrame {
+    pub fn empty(tick: u32, player: u8) -> Self {
+        Self {
+            tick,
+            player,
+            inputs: vec![],
+            sticks: vec![],
+            look_scale: None,
+            look: (0., 0.),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty() && self.sticks.is_empty() && self.look_scale.is_none() && self.look == (0., 0.)
+    }
+
+    // Replaces where the stick was moved to earlier in the frame, if it was.
+    pub fn set_stick(&mut self, stick: Stick, value: (f32, f32)) {
+        match self.sticks.iter_mut().find(|(moved, _)| *moved == stick) {
+            Some(moved) => moved.1 = value,
+            None => self.sticks.push((stick, value)),
+        }
+    }
+
+    // [kind][tick u32][player u8][look x f32][look y f32][count u8][inputs u8..]
+    // [count u8][sticks: stick u8, x f32, y f32..][scaled u8][look scale x f32, y f32 if scaled]
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = vec![PACKET_INPUT];
+        bytes.extend_from_slice(&self.tick.to_le_bytes());
+        bytes.push(self.player);
+        bytes.extend_from_slice(&self.look.0.to_le_bytes());
+        bytes.extend_from_slice(&self.look.1.to_le_bytes());
+        bytes.push(self.inputs.len() as u8);
+        for input in &self.inputs {
+            bytes.push(*input as u8);
+        }
+        bytes.push(self.sticks.len() as u8);
+        for (stick, (x, y)) in &self.sticks {
+            bytes.push(*stick as u8);
+            bytes.extend_from_slice(&x.to_le_bytes());
+            bytes.extend_from_slice(&y.to_le_bytes());
+        }
+        match self.look_scale {
+            Some((x, y)) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&x.to_le_bytes());
+                bytes.extend_from_slice(&y.to_le_bytes());
+            },
+            None => bytes.push(0),
+        }
+        bytes
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 16 || bytes[0] != PACKET_INPUT {
+            return None
+        }
+        let sticks_at = 15 + bytes[14] as usize;
+        if bytes.len() <= sticks_at {
+            return None
+        }
+        let scale_at = sticks_at + 1 + 9 * bytes[sticks_at] as usize;
+        let scaled = match bytes.get(scale_at) {
+            Some(0) => false,
+            Some(1) => true,
+            _ => return None,
+        };
+        if bytes.len() != scale_at + if scaled { 9 } else { 1 } {
+            return None
+        }
+        let mut inputs = vec![];
+        for code in &bytes[15..sticks_at] {
+            inputs.push(Input::from_u8(*code)?);
+        }
+        let mut sticks = vec![];
+        for stick in bytes[sticks_at + 1..scale_at].chunks(9) {
+            let value = (f32::from_bits(read_u32(&stick[1..5])), f32::from_bits(read_u32(&stick[5..9])));
+            sticks.push((Stick::from_u8(stick[0])?, value));
+        }
+        Some(Self {
+            tick: read_u32(&bytes[1..5]),
+            player: bytes[5],
+            inputs,
+            sticks,
+            look_scale: if scaled {
+                Some((f32::from_bits(read_u32(&bytes[scale_at + 1..scale_at + 5])), f32::from_bits(read_u32(&bytes[scale_at + 5..scale_at + 9]))))
+            } else {
+                None
+            },
+            look: (f32::from_bits(read_u32(&bytes[6..10])), f32::from_bits(read_u32(&bytes[10..14]))),
+        })
+    }
+
+    // Frames for players the universe doesn't have are dropped.
+    pub fn apply(&self, universe: &mut Universe) {
+        let player = self.player as usize;
+        if player >= universe.players().len() {
+            return
+        }
+        for input in &self.inputs {
+            universe.player_input(player, *input);
+        }
+        for (stick, value) in &self.sticks {
+            universe.stick_input(player, *stick, *value);
+        }
+        if let Some(scale) = self.look_scale {
+            universe.look_scale_input(player, scale);
+        }
+        if self.look != (0., 0.) {
+            universe.look_input(player, self.look.0, self.look.1);
+        }
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SyncMode {
+    // wait for every player's input before simulating a tick
+    Lockstep,
+    // predict missing input as "no change", and resimulate when it arrives
+    Rollback,
+}
+
+pub struct NetSession<T: Transport> {
+    transport: T,
+    mode: SyncMode,
+    local_player: usize,
+    num_players: usize,
+    input_delay: u32,
+
+    tick: u32, // next tick to simulate
+    confirmed: u32, // first tick not every player's input is known for yet
+    pending: InputFrame, // local input gathered for the next frame sent
+
+    frames: BTreeMap<u32, Vec<Option<InputFrame>>>,
+    states: BTreeMap<u32, Snapshot>, // rollback only, state before each tick
+    rollback_to: Option<u32>,
+
+    local_checksums: BTreeMap<u32, u32>,
+    remote_checksums: BTreeMap<u32, u32>,
+    desync: Option<u32>,
+}
+
+impl<T: Transport> NetSession<T> {
+    pub fn new(transport: T, mode: SyncMode, local_player: usize, num_players: usize, input_delay: u32) -> Self {
+        let mut frames = BTreeMap::new();
+        // nobody can have input for the first ticks, so every peer starts them empty
+        for tick in 0..input_delay {
+            frames.insert(tick, (0..num_players).map(|player| Some(InputFrame::empty(tick, player as u8))).collect());
+        }
+        Self {
+            transport,
+            mode,
+            local_player,
+            num_players,
+            input_delay,
+            tick: 0,
+            confirmed: input_delay,
+            pending: InputFrame::empty(0, local_player as u8),
+            frames,
+            states: BTreeMap::new(),
+            rollback_to: None,
+            local_checksums: BTreeMap::new(),
+            remote_checksums: BTreeMap::new(),
+            desync: None,
+        }
+    }
+
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    pub fn tick(&self) -> u32 {
+        self.tick
+    }
+
+    pub fn desync(&self) -> Option<u32> {
+        self.desync
+    }
+
+    pub fn add_input(&mut self, input: Input) {
+        self.pending.inputs.push(input);
+    }
+
+    // The local player's look sensitivity, which every peer needs to turn them the same.
+    pub fn set_look_scale(&mut self, x: f32, y: f32) {
+        self.pending.look_scale = Some((x, y));
+    }
+
+    // Where a stick's been moved to, deadzone already taken out. Only the
+    // last position before the next frame is sent goes out.
+    pub fn set_stick(&mut self, stick: Stick, x: f32, y: f32) {
+        self.pending.set_stick(stick, (x, y));
+    }
+
+    pub fn add_look(&mut self, movement_x: f32, movement_y: f32) {
+        self.pending.look.0 += movement_x;
+        self.pending.look.1 += movement_y;
+    }
+
+    // Sends local input, handles incoming packets, and simulates at most one
+    // new tick. Returns whether a tick was simulated.
+    pub fn advance(&mut self, universe: &mut Universe) -> bool {
+        let target = self.tick + self.input_delay;
+        if !self.has_frame(target, self.local_player) {
+            let mut frame = std::mem::replace(&mut self.pending, InputFrame::empty(0, self.local_player as u8));
+            frame.tick = target;
+            self.transport.send(frame.serialize());
+            self.insert_frame(frame);
+        }
+
+        while let Some(packet) = self.transport.receive() {
+            self.handle_packet(&packet);
+        }
+
+        match self.mode {
+            SyncMode::Lockstep => {
+                if self.confirmed <= self.tick {
+                    return false
+                }
+                if self.tick.is_multiple_of(CHECKSUM_INTERVAL) {
+                    self.record_checksum(self.tick, universe.checksum());
+                }
+                self.simulate(universe, self.tick);
+                self.tick += 1;
+                self.frames = self.frames.split_off(&self.tick);
+                true
+            },
+            SyncMode::Rollback => {
+                if let Some(from) = self.rollback_to.take() {
+                    // input from before the oldest kept state can't be replayed,
+                    // so the peers can't agree any more
+                    let snapshot = match self.states.get(&from) {
+                        Some(snapshot) => snapshot.clone(),
+                        None => {
+                            self.desync = self.desync.or(Some(from));
+                            return false
+                        },
+                    };
+                    // those ticks already reported their events when first run
+                    let events = universe.pending_events();
+                    universe.restore(&snapshot);
+                    for tick in from..self.tick {
+                        self.states.insert(tick, universe.snapshot());
+                        self.simulate(universe, tick);
+                    }
+                    universe.set_pending_events(events);
+                }
+                self.checksum_confirmed(universe);
+                if self.tick >= self.confirmed + MAX_PREDICTION {
+                    return false
+                }
+                self.states.insert(self.tick, universe.snapshot());
+                self.simulate(universe, self.tick);
+                self.tick += 1;
+
+                let oldest = self.confirmed.min(self.tick);
+                self.frames = self.frames.split_off(&oldest);
+                self.states = self.states.split_off(&oldest);
+                true
+            },
+        }
+    }
+
+    fn simulate(&self, universe: &mut Universe, tick: u32) {
+        if let Some(frames) = self.frames.get(&tick) {
+            for frame in frames.iter().flatten() {
+                frame.apply(universe);
+            }
+        }
+        universe.step(TICK_TIME);
+    }
+
+    fn has_frame(&self, tick: u32, player: usize) -> bool {
+        match self.frames.get(&tick) {
+            Some(frames) => frames[player].is_some(),
+            None => false,
+        }
+    }
+
+    fn insert_frame(&mut self, frame: InputFrame) {
+        let (tick, player) = (frame.tick, frame.player as usize);
+        if player >= self.num_players || tick < self.confirmed || self.has_frame(tick, player) {
+            return
+        }
+        // rollback predicted this frame as empty, so only real input forces a resimulation
+        if self.mode == SyncMode::Rollback && tick < self.tick && !frame.is_empty() {
+            self.rollback_to = Some(self.rollback_to.map_or(tick, |from| from.min(tick)));
+        }
+        let num_players = self.num_players;
+        self.frames.entry(tick).or_insert_with(|| vec![None; num_players])[player] = Some(frame);
+
+        while self.frames.get(&self.confirmed).is_some_and(|frames| frames.iter().all(|frame| frame.is_some())) {
+            self.confirmed += 1;
+        }
+    }
+
+    fn handle_packet(&mut self, packet: &[u8]) {
+        match packet.first() {
+            Some(&PACKET_INPUT) => {
+                if let Some(frame) = InputFrame::deserialize(packet) {
+                    self.insert_frame(frame);
+                }
+            },
+            Some(&PACKET_CHECKSUM) if packet.len() == 9 => {
+                let tick = read_u32(&packet[1..5]);
+                let checksum = read_u32(&packet[5..9]);
+                self.remote_checksums.insert(tick, checksum);
+                self.compare_checksum(tick);
+            },
+            _ => (),
+        }
+    }
+
+    // Under rollback a tick's state is only final once every input before it is known.
+    fn checksum_confirmed(&mut self, universe: &Universe) {
+        let last_checked = self.local_checksums.keys().next_back().map_or(0, |tick| tick + 1);
+        let first = last_checked.div_ceil(CHECKSUM_INTERVAL) * CHECKSUM_INTERVAL;
+        for tick in (first..=self.confirmed.min(self.tick)).step_by(CHECKSUM_INTERVAL as usize) {
+            let checksum = if tick == self.tick {
+                universe.checksum()
+            } else {
+                match self.states.get(&tick) {
+                    Some(snapshot) => Universe::checksum_players(&snapshot.players),
+                    None => continue,
+                }
+            };
+            self.record_checksum(tick, checksum);
+        }
+    }
+
+    fn record_checksum(&mut self, tick: u32, checksum: u32) {
+        self.local_checksums.insert(tick, checksum);
+        let mut packet = vec![PACKET_CHECKSUM];
+        packet.extend_from_slice(&tick.to_le_bytes());
+        packet.extend_from_slice(&checksum.to_le_bytes());
+        self.transport.send(packet);
+        self.compare_checksum(tick);
+    }
+
+    fn compare_checksum(&mut self, tick: u32) {
+        if let (Some(local), Some(remote)) = (self.local_checksums.get(&tick), self.remote_checksums.get(&tick)) {
+            if local != remote && self.desync.is_none() {
+                self.desync = Some(tick);
+            }
+        }
+    }
+}
+
+#[wasm_bindgen]
+pub struct NetClient {
+    session: NetSession<QueueTransport>,
+}
+
+#[wasm_bindgen]
+impl NetClient {
+    pub fn new(local_player: usize, num_players: usize, input_delay: u32, rollback: bool) -> Self {
+        let mode = if rollback { SyncMode::Rollback } else { SyncMode::Lockstep };
+        Self {
+            session: NetSession::new(QueueTransport::new(), mode, local_player, num_players, input_delay),
+        }
+    }
+
+    pub fn input(&mut self, input: Input) {
+        self.session.add_input(input);
+    }
+
+    pub fn mouse_look(&mut self, movement_x: f32, movement_y: f32) {
+        self.session.add_look(movement_x, movement_y);
+    }
+
+    // As Universe::stick_input takes it: move y is forward, look y is down.
+    pub fn set_stick(&mut self, stick: Stick, x: f32, y: f32) {
+        self.session.set_stick(stick, x, y);
+    }
+
+    // Sensitivity for the look stick and keys, from the local settings.
+    pub fn set_look_scale(&mut self, x: f32, y: f32) {
+        self.session.set_look_scale(x, y);
+    }
+
+    pub fn advance(&mut self, universe: &mut Universe) -> bool {
+        self.session.advance(universe)
+    }
+
+    pub fn receive_packet(&mut self, packet: &[u8]) {
+        self.session.transport().incoming.push_back(packet.to_vec());
+    }
+
+    pub fn next_packet(&mut self) -> Option<Vec<u8>> {
+        self.session.transport().outgoing.pop_front()
+    }
+
+    pub fn tick(&self) -> u32 {
+        self.session.tick()
+    }
+
+    pub fn desync_tick(&self) -> Option<u32> {
+        self.session.desync()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::master::Master;
+
+    const INPUT_DELAY: u32 = 2;
+    const TICKS: u32 = 300;
+
+    // Each player's input for a tick, different enough that any desync shows.
+    // The last ticks are left quiet, so what each client predicts for input
+    // it hasn't received by the end is right.
+    fn script(session: &mut NetSession<LoopbackTransport>, player: usize, tick: u32) {
+        if tick + INPUT_DELAY + MAX_PREDICTION >= TICKS {
+            return
+        }
+        match (player, tick) {
+            (0, 5) => session.add_input(Input::GoForward),
+            (0, 40) => session.add_input(Input::Jump),
+            (0, 60) => session.add_input(Input::Cast),
+            (0, 90) => session.add_input(Input::StopForward),
+            (1, 10) => session.add_input(Input::GoRight),
+            (1, 15) => session.set_stick(Stick::Look, 0.5, 0.),
+            (1, 25) => session.set_stick(Stick::Look, 0., 0.),
+            (1, 50) => session.add_input(Input::GoForward),
+            (1, 70) => session.add_input(Input::Jump),
+            (1, 120) => session.add_input(Input::StopRight),
+            (1, 250) => {
+                session.set_stick(Stick::Move, 1., 1.);
+                session.set_stick(Stick::Move, 0.4, 0.8);
+            },
+            _ => (),
+        }
+        if tick % 7 == player as u32 {
+            session.add_look(3. + player as f32, -1.5);
+        }
+    }
+
+    // Two clients over loopback. Player 1 stalls for a stretch, so its input
+    // reaches player 0 late, after player 0 has already run those ticks.
+    // Also returns how far player 0 got ahead of player 1.
+    fn run(mode: SyncMode) -> (Universe, Universe, NetSession<LoopbackTransport>, NetSession<LoopbackTransport>, u32) {
+        let master = Master::new();
+        let level = master.level(0).unwrap();
+        let mut universes = vec![Universe::new(level, 2), Universe::new(level, 2)];
+        let mut transports = LoopbackTransport::network(2);
+        let transport_1 = transports.pop().unwrap();
+        let transport_0 = transports.pop().unwrap();
+        let mut sessions = vec![
+            NetSession::new(transport_0, mode, 0, 2, INPUT_DELAY),
+            NetSession::new(transport_1, mode, 1, 2, INPUT_DELAY),
+        ];
+        let mut scripted = [None; 2];
+        let mut rounds = 0;
+        let mut lead = 0;
+        while sessions.iter().any(|session| session.tick() < TICKS) {
+            rounds += 1;
+            assert!(rounds < TICKS * 10, "the sessions stopped advancing");
+            for player in 0..2 {
+                let stalled = player == 1 && rounds > 45 && rounds < 52;
+                if stalled || sessions[player].tick() >= TICKS {
+                    continue
+                }
+                let tick = sessions[player].tick();
+                if scripted[player] != Some(tick) {
+                    script(&mut sessions[player], player, tick);
+                    scripted[player] = Some(tick);
+                }
+                sessions[player].advance(&mut universes[player]);
+            }
+            lead = lead.max(sessions[0].tick().saturating_sub(sessions[1].tick()));
+        }
+        let universe_1 = universes.pop().unwrap();
+        let universe_0 = universes.pop().unwrap();
+        let session_1 = sessions.pop().unwrap();
+        let session_0 = sessions.pop().unwrap();
+        (universe_0, universe_1, session_0, session_1, lead)
+    }
+
+    fn check(mode: SyncMode) {
+        let (universe_0, universe_1, session_0, session_1, lead) = run(mode);
+        if mode == SyncMode::Rollback {
+            // player 0 ran ticks before player 1's input for them arrived
+            assert!(lead > INPUT_DELAY);
+        }
+        assert_eq!(session_0.desync(), None);
+        assert_eq!(session_1.desync(), None);
+        // the checksums were exchanged, not just never compared
+        let compared = session_0.local_checksums.keys().filter(|tick| session_0.remote_checksums.contains_key(tick)).count();
+        assert!(compared > 0);
+        assert_eq!(universe_0.checksum(), universe_1.checksum());
+        assert_ne!(universe_0.players()[0].position.z, universe_0.players()[1].position.z);
+        // only the last stick position set for a frame was sent
+        assert_eq!(universe_0.players()[1].stick(Stick::Move), (0.4, 0.8));
+    }
+
+    #[test]
+    fn lockstep_clients_agree() {
+        check(SyncMode::Lockstep);
+    }
+
+    #[test]
+    fn rollback_clients_agree_after_late_input() {
+        check(SyncMode::Rollback);
+    }
+
+    #[test]
+    fn frames_for_missing_players_are_dropped() {
+        let master = Master::new();
+        let mut universe = Universe::new(master.level(0).unwrap(), 1);
+        let mut frame = InputFrame::empty(0, 3);
+        frame.inputs.push(Input::Jump);
+        frame.look = (10., 0.);
+        frame.apply(&mut universe);
+        assert_eq!(universe.players().len(), 1);
+    }
+}
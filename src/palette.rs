@@ -0,0 +1,108 @@
+pub type Color = [f32; 4];
+
+// what an empty color list falls back to
+const FALLBACK: Color = [0.5, 0.5, 0.5, 1.0];
+
+#[derive(Clone)]
+pub struct Palette {
+    pub name: String,
+    materials: Vec<Color>, // indexed by a block's material id
+    win: Color,
+    players: Vec<Color>,
+    grapple: Color,
+}
+
+impl Palette {
+    // Empty material or player colors get FALLBACK, so lookups always have
+    // a color to wrap around to.
+    pub fn new(name: &str, materials: Vec<Color>, win: Color, players: Vec<Color>, grapple: Color) -> Self {
+        let materials = if materials.is_empty() { vec![FALLBACK] } else { materials };
+        let players = if players.is_empty() { vec![FALLBACK] } else { players };
+        Self {
+            name: String::from(name),
+            materials,
+            win,
+            players,
+            grapple,
+        }
+    }
+
+    pub fn classic() -> Self {
+        Self::new(
+            "classic",
+            vec![
+                [0.5, 0.5, 0.5, 1.0],
+                [1.0, 0.0, 0.0, 1.0],
+                [0.0, 1.0, 0.0, 1.0],
+                [0.0, 0.0, 1.0, 1.0],
+                [1.0, 1.0, 0.0, 1.0],
+                [1.0, 0.0, 1.0, 1.0],
+                [0.0, 1.0, 1.0, 1.0],
+            ],
+            [1.0, 0.84, 0.0, 1.0],
+            vec![
+                [0.9, 0.3, 0.1, 1.0],
+                [0.2, 0.4, 0.9, 1.0],
+                [0.3, 0.8, 0.3, 1.0],
+                [0.8, 0.3, 0.8, 1.0],
+            ],
+            [1.0, 1.0, 1.0, 1.0],
+        )
+    }
+
+    pub fn dusk() -> Self {
+        Self::new(
+            "dusk",
+            vec![
+                [0.25, 0.22, 0.3, 1.0],
+                [0.55, 0.35, 0.6, 1.0],
+                [0.85, 0.5, 0.4, 1.0],
+                [0.4, 0.45, 0.7, 1.0],
+                [0.9, 0.7, 0.5, 1.0],
+            ],
+            [0.3, 1.0, 0.6, 1.0],
+            vec![
+                [1.0, 0.6, 0.2, 1.0],
+                [0.5, 0.8, 1.0, 1.0],
+            ],
+            [1.0, 0.9, 0.7, 1.0],
+        )
+    }
+
+    pub fn by_name(name: &str) -> Option<Self> {
+        match name {
+            "classic" => Some(Self::classic()),
+            "dusk" => Some(Self::dusk()),
+            _ => None,
+        }
+    }
+
+    // Unknown ids wrap around rather than panicking on hand-written levels.
+    pub fn material(&self, id: usize) -> Color {
+        self.materials[id % self.materials.len()]
+    }
+
+    pub fn win(&self) -> Color {
+        self.win
+    }
+
+    pub fn player(&self, index: usize) -> Color {
+        self.players[index % self.players.len()]
+    }
+
+    pub fn grapple(&self) -> Color {
+        self.grapple
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_palettes_still_have_colors() {
+        let palette = Palette::new("empty", vec![], FALLBACK, vec![], FALLBACK);
+        assert_eq!(palette.material(3), FALLBACK);
+        assert_eq!(palette.player(1), FALLBACK);
+    }
+}
@@ -1,10 +1,20 @@
 use wasm_bindgen::prelude::*;
 use super::utils::Vec3;
-use super::grapple::Grapple;
+use super::grapple::{Grapple, Hand, HANDS};
 use super::block::Block;
 use super::utils::AABB;
 use super::log;
+use super::snapshot::{StateWriter, StateReader};
+use super::events::{Event, EventKind};
+use super::input::{InputState, Stick};
 
+pub const LOOK_SPEED: f32 = 0.0008; // radians per pixel of mouse movement
+
+// per tick, and what the generator uses to work out reach
+pub const MOVE_SPEED: f32 = 0.1;
+pub const JUMP_SPEED: f32 = 0.2;
+
+#[derive(Clone)]
 pub struct Player {
     look_spd: f32,
     move_acc: f32,
@@ -16,16 +26,19 @@ pub struct Player {
 
 	pub position: Vec3,
 	pub velocity: Vec3,
-	h_vel: f32,
-    d_vel: f32,
+    input: InputState,
+    look_scale: (f32, f32), // sensitivity for the look stick and keys, x and y
     theta: f32,
     phi: f32,
     
     pub dims: Vec3,
     on_ground: bool, // set to false each update, and set true if it is colliding with something below it
 
-    pub grapple: Option<Grapple>,
-    pulling: bool,
+    pub grapples: [Option<Grapple>; HANDS], // indexed by Hand
+    pulling: [bool; HANDS],
+
+    pub checkpoint: Option<usize>, // furthest checkpoint reached
+    pub events: Vec<Event>, // drained by the universe each step
 }
 
 impl AABB for Player {
@@ -51,54 +64,126 @@ impl Player {
     pub fn new(position: Vec3) -> Self {
         log("Created Player!");
         Self {
-            look_spd: 0.0008,
+            look_spd: LOOK_SPEED,
             move_acc: 0.04,
             friction: 0.03,
             air_res: 0.0015,
-            move_spd: 0.1,
+            move_spd: MOVE_SPEED,
             term_spd: 0.5,
-            jump_spd: 0.2,
+            jump_spd: JUMP_SPEED,
 
             position,
             velocity: Vec3::new(0., 0., 0.),
-            h_vel: 0.,
-            d_vel: 0.,
+            input: InputState::new(),
+            look_scale: (1., 1.),
             theta: 0.,
             phi: 0.,
             
             dims: Vec3::new(0.5, 2., 0.5),
             on_ground: false,
 
-            grapple: None,
-            pulling: false,
+            grapples: [None, None],
+            pulling: [false; HANDS],
+
+            checkpoint: None,
+            events: vec![],
+        }
+    }
+
+    pub fn write_state(&self, writer: &mut StateWriter) {
+        for value in &[self.look_spd, self.move_acc, self.friction, self.air_res, self.move_spd, self.term_spd, self.jump_spd] {
+            writer.f32(*value);
+        }
+        writer.vec3(&self.position);
+        writer.vec3(&self.velocity);
+        self.input.write_state(writer);
+        writer.f32(self.look_scale.0);
+        writer.f32(self.look_scale.1);
+        writer.f32(self.theta);
+        writer.f32(self.phi);
+        writer.vec3(&self.dims);
+        writer.bool(self.on_ground);
+        for hand in 0..HANDS {
+            writer.bool(self.pulling[hand]);
+            writer.bool(self.grapples[hand].is_some());
+            if let Some(grapple) = &self.grapples[hand] {
+                grapple.write_state(writer);
+            }
+        }
+        // checkpoints are counted from 1 so 0 can mean none
+        writer.usize(self.checkpoint.map_or(0, |checkpoint| checkpoint + 1));
+    }
+
+    pub fn read_state(reader: &mut StateReader) -> Option<Self> {
+        let mut player = Self {
+            look_spd: reader.f32()?,
+            move_acc: reader.f32()?,
+            friction: reader.f32()?,
+            air_res: reader.f32()?,
+            move_spd: reader.f32()?,
+            term_spd: reader.f32()?,
+            jump_spd: reader.f32()?,
+            position: reader.vec3()?,
+            velocity: reader.vec3()?,
+            input: InputState::read_state(reader)?,
+            look_scale: (reader.f32()?, reader.f32()?),
+            theta: reader.f32()?,
+            phi: reader.f32()?,
+            dims: reader.vec3()?,
+            on_ground: reader.bool()?,
+            pulling: [false; HANDS],
+            grapples: [None, None],
+            checkpoint: None,
+            events: vec![],
+        };
+        for hand in 0..HANDS {
+            player.pulling[hand] = reader.bool()?;
+            player.grapples[hand] = if reader.bool()? { Some(Grapple::read_state(reader)?) } else { None };
         }
+        player.checkpoint = reader.usize()?.checked_sub(1);
+        Some(player)
     }
 
-    pub fn cast_grapple(&mut self) {
-        match &mut self.grapple {
+    pub fn look_dir(&self) -> Vec3 {
+        Vec3::new(self.theta.sin() * self.phi.cos(), self.phi.sin(), self.theta.cos() * self.phi.cos())
+    }
+
+    pub fn cast_grapple(&mut self, hand: Hand) {
+        self.cast_grapple_toward(hand, self.look_dir());
+    }
+
+    // cast_dir is a unit vector, e.g. the look direction adjusted by aim assist
+    pub fn cast_grapple_toward(&mut self, hand: Hand, cast_dir: Vec3) {
+        match &mut self.grapples[hand as usize] {
             None => {
-                let cast_dir = Vec3::new(self.theta.sin() * self.phi.cos(), self.phi.sin(), self.theta.cos() * self.phi.cos());
-                self.grapple = Some(Grapple::new(self.position.clone(), self.position.clone() + cast_dir));
+                self.grapples[hand as usize] = Some(Grapple::new(self.position.clone(), self.position.clone() + cast_dir));
+                self.events.push(Event::new(EventKind::GrappleCast).for_hand(hand));
                 log("Created grapple!");
             },
             Some(_) => {
-                self.grapple = None;
+                self.grapples[hand as usize] = None;
                 log("Destroyed grapple!");
             },
         }
     }
 
-    pub fn pull_grapple(&mut self) {
-        self.pulling = true;
+    pub fn pull_grapple(&mut self, hand: Hand) {
+        self.pulling[hand as usize] = true;
         log("Pulling grapple!");
     }
 
-    pub fn release_grapple(&mut self) {
-        self.pulling = false;
+    // Stops reeling in. A hooked grapple stays hooked, rope and all.
+    pub fn stop_pulling(&mut self, hand: Hand) {
+        self.pulling[hand as usize] = false;
         log("Released grapple!");
     }
 
-    pub fn update(&mut self, blocks: &Vec<Block>, gravity: f32, elapsed_time: f32) {
+    pub fn grapple(&self, hand: Hand) -> Option<&Grapple> {
+        self.grapples[hand as usize].as_ref()
+    }
+
+    // `others` are the boxes of other players, when they're solid
+    pub fn update(&mut self, blocks: &Vec<Block>, others: &[Block], gravity: f32, elapsed_time: f32) {
         let fps_throttle = 1000. / 60.;
         let time_step = elapsed_time / fps_throttle;
 
@@ -124,19 +209,27 @@ impl Player {
             }
         }
 
+        // USER LOOK, from the look stick and keys; the mouse turns as it moves
+
+        self.look_around(time_step);
+
         // USER MOVEMENT
 
         let hd_vel = Vec3::new(self.velocity.x, 0., self.velocity.z);
         let h_dir = Vec3::new(self.theta.cos(), 0., -self.theta.sin());
         let d_dir = Vec3::new(self.theta.sin(), 0., self.theta.cos());
 
-        let move_dir = (h_dir * self.h_vel + d_dir * self.d_vel).unit();
+        let (strafe, forward) = self.input.movement();
+        let move_dir = (h_dir * strafe + d_dir * forward).unit();
         let move_acc = move_dir * self.move_acc;
-
-        if hd_vel.length() <= self.move_spd + 0.001 { // ApproxEq
-            if (hd_vel + move_acc).length() >= self.move_spd {
-                self.velocity.x = ((hd_vel + move_acc).unit() * self.move_spd).x;
-                self.velocity.z = ((hd_vel + move_acc).unit() * self.move_spd).z;
+        // a stick pushed part way runs at part speed
+        let push = (strafe * strafe + forward * forward).sqrt();
+        let move_spd = if push > 0. { self.move_spd * push.min(1.) } else { self.move_spd };
+
+        if hd_vel.length() <= move_spd + 0.001 { // ApproxEq
+            if (hd_vel + move_acc).length() >= move_spd {
+                self.velocity.x = ((hd_vel + move_acc).unit() * move_spd).x;
+                self.velocity.z = ((hd_vel + move_acc).unit() * move_spd).z;
             } else {
                 self.velocity += move_acc * time_step; // ADFSDFSDD
             }
@@ -147,22 +240,54 @@ impl Player {
 
         // GRAPPLE
 
-        match &mut self.grapple {
-            None => self.pulling = false,
-            Some(grapple) => {
-                if grapple.hooked {
-                    let grapple_dir = (grapple.end - self.position).unit();
-                    if self.velocity.dot(&grapple_dir) < 0. {
-                        self.velocity = self.velocity - self.velocity.project_onto(&grapple_dir); // project onto the plane of the normal
-                    }
-                    if self.pulling {
-                        self.velocity += grapple_dir * grapple.pull * time_step;
-                    }
-                } else {
-                    if (grapple.end - self.position).length() > grapple.length {
-                        self.grapple = None;
+        // pulls from both hands add up, and each rope stops the player moving away from its anchor
+        let mut anchor_dirs = vec![];
+        let mut pull_force = Vec3::new(0., 0., 0.);
+        for hand in Hand::all().iter() {
+            let index = *hand as usize;
+            match &mut self.grapples[index] {
+                None => self.pulling[index] = false,
+                Some(grapple) => {
+                    if grapple.hooked {
+                        let grapple_dir = (grapple.anchor() - self.position).unit();
+                        anchor_dirs.push(grapple_dir);
+                        if self.pulling[index] && !grapple.pulls_target {
+                            pull_force += grapple_dir * grapple.pull;
+                        }
                     } else {
-                        grapple.cast(blocks, time_step);
+                        if (grapple.end - self.position).length() > grapple.length {
+                            self.grapples[index] = None;
+                            self.events.push(Event::new(EventKind::GrappleBroke).for_hand(*hand));
+                        } else {
+                            grapple.cast(blocks, &self.position, time_step);
+                            if grapple.hooked {
+                                self.events.push(Event::hooked(grapple.end).for_hand(*hand));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        // a second pass so removing one rope's outward part can't leave the other's
+        for _pass in 0..anchor_dirs.len() {
+            for grapple_dir in &anchor_dirs {
+                if self.velocity.dot(grapple_dir) < 0. {
+                    self.velocity = self.velocity - self.velocity.project_onto(grapple_dir); // project onto the plane of the normal
+                }
+            }
+        }
+        self.velocity += pull_force * time_step;
+
+        // the rope never stretches, so whatever would carry the player past its
+        // length is taken off the velocity, where the collisions still see it
+        if time_step > 0. {
+            for grapple in self.grapples.iter().flatten() {
+                if grapple.hooked && grapple.target.is_none() {
+                    let anchor = grapple.anchor();
+                    let to_next = self.position + self.velocity * time_step - anchor;
+                    if to_next.length() > grapple.free_length() {
+                        let next = anchor + to_next.unit() * grapple.free_length();
+                        self.velocity = (next - self.position) / time_step;
                     }
                 }
             }
@@ -170,9 +295,11 @@ impl Player {
 
         // COLLISIONS
 
+        let was_on_ground = self.on_ground;
+        let fall_speed = -self.velocity.y;
         self.on_ground = false;
 
-        for block in blocks {
+        for block in blocks.iter().chain(others) {
 
             let collision_dir = self.collision(block, &(self.velocity * time_step));
 
@@ -189,55 +316,84 @@ impl Player {
 
         }
 
+        if self.on_ground && !was_on_ground && fall_speed > 0. {
+            self.events.push(Event::landed(fall_speed));
+        }
+
         // TERMINAL VELOCITY
         if self.velocity.length() > self.term_spd {
             self.velocity = self.velocity.unit() * self.term_spd;
         }
 
         // MOVEMENT
+        let prev_position = self.position;
         self.position = self.position + self.velocity * time_step;
+
+        // ROPE
+        for grapple in self.grapples.iter_mut().flatten() {
+            // ropes to players just follow them
+            if grapple.hooked && grapple.target.is_none() {
+                grapple.wrap(blocks, &prev_position, &self.position);
+                // it shortens as the player closes in
+                let free_length = grapple.free_length();
+                let distance = (self.position - grapple.anchor()).length();
+                if distance < free_length {
+                    grapple.rope_length -= free_length - distance;
+                }
+            }
+        }
     }
 
+    // Holding opposite directions cancels out, and letting go of one runs
+    // the other way.
     pub fn go(&mut self, go: Go) {
         match go {
-            Go::Left => self.h_vel = -self.move_acc,
-            Go::Forward => self.d_vel = self.move_acc,
-            Go::Right => self.h_vel = self.move_acc,
-            Go::Back => self.d_vel = -self.move_acc,
-            Go::Jump => if self.on_ground { self.velocity.y = self.jump_spd; self.on_ground = false; } else { },
+            Go::Jump => if self.on_ground { self.velocity.y = self.jump_spd; self.on_ground = false; self.events.push(Event::new(EventKind::Jumped)); } else { },
+            _ => self.input.set_moving(go as usize, true),
         }
     }
 
     pub fn stop(&mut self, go: Go) {
         match go {
-            Go::Left => {
-                if self.h_vel == -self.move_acc {
-                    self.h_vel = 0.;
-                }
-            },
-            Go::Forward => {
-                if self.d_vel == self.move_acc {
-                    self.d_vel = 0.;
-                }
-            },
-            Go::Right => {
-                if self.h_vel == self.move_acc {
-                    self.h_vel = 0.;
-                }
-            },
-            Go::Back => {
-                if self.d_vel == -self.move_acc {
-                    self.d_vel = 0.;
-                }
-            },
-            _ => (),
+            Go::Jump => (),
+            _ => self.input.set_moving(go as usize, false),
         }
     }
 
+    // Left, right, up, down, turning for as long as it's held.
+    pub fn look(&mut self, direction: usize, held: bool) {
+        self.input.set_looking(direction, held);
+    }
+
+    pub fn stick(&self, stick: Stick) -> (f32, f32) {
+        self.input.stick(stick)
+    }
+
+    // Deadzone already taken out.
+    pub fn set_stick(&mut self, stick: Stick, value: (f32, f32)) {
+        self.input.set_stick(stick, value);
+    }
+
+    pub fn look_scale(&self) -> (f32, f32) {
+        self.look_scale
+    }
+
+    pub fn set_look_scale(&mut self, scale: (f32, f32)) {
+        self.look_scale = scale;
+    }
+
     pub fn mouse_look(&mut self, movement_x: f32, movement_y: f32) {
-        let movement_y = -movement_y;
-        let del_theta = movement_x * self.look_spd;
-        let del_phi = movement_y * self.look_spd;
+        self.turn(movement_x * self.look_spd, -movement_y * self.look_spd);
+    }
+
+    // Turns by the held look keys and look stick. Players held at spawn still
+    // look around, so this runs on its own during the countdown.
+    pub fn look_around(&mut self, time_step: f32) {
+        let (look_x, look_y) = self.input.look(time_step, self.look_scale);
+        self.turn(look_x, -look_y);
+    }
+
+    fn turn(&mut self, del_theta: f32, del_phi: f32) {
         self.theta += del_theta;
         self.phi = {
             if (self.phi + del_phi).abs() < std::f32::consts::PI / 2. {
@@ -252,6 +408,18 @@ impl Player {
         self.position.to_vec()
     }
 
+    pub fn pulling(&self, hand: Hand) -> bool {
+        self.pulling[hand as usize]
+    }
+
+    pub fn input(&self) -> &InputState {
+        &self.input
+    }
+
+    pub fn on_ground(&self) -> bool {
+        self.on_ground
+    }
+
     pub fn theta(&self) -> f32 {
         self.theta
     }
@@ -259,4 +427,22 @@ impl Player {
     pub fn phi(&self) -> f32 {
         self.phi
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_short_rope_cant_pull_the_player_through_the_floor() {
+        let blocks = vec![Block::new(Vec3::new(-10., -1., -10.), Vec3::new(20., 1., 20.), 0)];
+        let mut player = Player::new(Vec3::new(0., 1., 0.));
+        // low down and far off, so the rope pulls down into the floor as well as across
+        let anchor = Vec3::new(5., 0.5, 0.);
+        let mut grapple = Grapple::new(player.position, anchor);
+        grapple.hooked = true;
+        grapple.rope_length = 1.;
+        player.grapples[Hand::Left as usize] = Some(grapple);
+        player.update(&blocks, &[], -0.01, 1000. / 60.);
+        assert!(player.min().y > -1e-3);
+    }
+}
@@ -0,0 +1,100 @@
+use super::netcode::InputFrame;
+use super::universe::{Universe, Input};
+use super::snapshot::{StateWriter, StateReader};
+use super::input::Stick;
+use super::master::COUNTDOWN_TICKS;
+
+// One update of a run: the inputs in the order they came, then the step.
+#[derive(Clone)]
+pub struct ReplayStep {
+    pub frames: Vec<InputFrame>,
+    pub elapsed: f32, // ms
+}
+
+// Everything needed to play a run back exactly from the level's start.
+#[derive(Clone)]
+pub struct Replay {
+    pub countdown: u32, // ticks left before go when recording started
+    pub steps: Vec<ReplayStep>,
+}
+
+impl Replay {
+    pub fn new(countdown: u32) -> Self {
+        Self {
+            countdown,
+            steps: vec![],
+        }
+    }
+
+    // Plays the whole run into a universe fresh from the same level, counting
+    // down first as the run did.
+    pub fn play(&self, universe: &mut Universe) {
+        universe.set_countdown(self.countdown);
+        for step in &self.steps {
+            for frame in &step.frames {
+                frame.apply(universe);
+            }
+            universe.step(step.elapsed);
+        }
+    }
+
+    pub fn write_state(&self, writer: &mut StateWriter) {
+        writer.u32(self.countdown);
+        writer.usize(self.steps.len());
+        for step in &self.steps {
+            writer.f32(step.elapsed);
+            writer.usize(step.frames.len());
+            for frame in &step.frames {
+                writer.u32(frame.tick);
+                writer.u32(frame.player as u32);
+                writer.f32(frame.look.0);
+                writer.f32(frame.look.1);
+                writer.usize(frame.inputs.len());
+                for input in &frame.inputs {
+                    writer.u32(*input as u32);
+                }
+                writer.usize(frame.sticks.len());
+                for (stick, (x, y)) in &frame.sticks {
+                    writer.u32(*stick as u32);
+                    writer.f32(*x);
+                    writer.f32(*y);
+                }
+                writer.bool(frame.look_scale.is_some());
+                if let Some((x, y)) = frame.look_scale {
+                    writer.f32(x);
+                    writer.f32(y);
+                }
+            }
+        }
+    }
+
+    // Save data version 1 had no sticks or look scales, and every recorded run
+    // counted down from COUNTDOWN_TICKS.
+    pub fn read_state(reader: &mut StateReader, version: u32) -> Option<Self> {
+        let countdown = if version >= 2 { reader.u32()? } else { COUNTDOWN_TICKS };
+        let mut steps = vec![];
+        for _step in 0..reader.usize()? {
+            let elapsed = reader.f32()?;
+            let mut frames = vec![];
+            for _frame in 0..reader.usize()? {
+                let mut frame = InputFrame::empty(reader.u32()?, reader.u32()? as u8);
+                frame.look = (reader.f32()?, reader.f32()?);
+                for _input in 0..reader.usize()? {
+                    frame.inputs.push(Input::from_u8(reader.u32()? as u8)?);
+                }
+                if version >= 2 {
+                    for _stick in 0..reader.usize()? {
+                        let stick = Stick::from_u8(reader.u32()? as u8)?;
+                        frame.sticks.push((stick, (reader.f32()?, reader.f32()?)));
+                    }
+                }
+                if version >= 2 && reader.bool()? {
+                    frame.look_scale = Some((reader.f32()?, reader.f32()?));
+                }
+                frames.push(frame);
+            }
+            steps.push(ReplayStep { frames, elapsed });
+        }
+        Some(Self { countdown, steps })
+    }
+}
@@ -0,0 +1,43 @@
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq)]
+pub enum PlayerGrapple {
+    Off,
+    PullSelf, // hooking a player pulls you to them
+    PullThem, // hooking a player drags them to you
+}
+
+// How players interact with each other, set per level and overridable per lobby.
+#[derive(Clone, Copy)]
+pub struct Rules {
+    pub player_collision: bool,
+    pub player_grapple: PlayerGrapple,
+}
+
+impl PlayerGrapple {
+    pub fn from_u8(code: u8) -> Option<PlayerGrapple> {
+        match code {
+            0 => Some(PlayerGrapple::Off),
+            1 => Some(PlayerGrapple::PullSelf),
+            2 => Some(PlayerGrapple::PullThem),
+            _ => None,
+        }
+    }
+}
+
+impl Rules {
+    pub fn non_contact() -> Self {
+        Self {
+            player_collision: false,
+            player_grapple: PlayerGrapple::Off,
+        }
+    }
+
+    pub fn contact() -> Self {
+        Self {
+            player_collision: true,
+            player_grapple: PlayerGrapple::PullThem,
+        }
+    }
+}
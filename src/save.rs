@@ -0,0 +1,231 @@
+use std::collections::{BTreeMap, BTreeSet};
+use super::catalog::Medal;
+use super::replay::Replay;
+use super::settings::Settings;
+use super::bindings::Bindings;
+use super::snapshot::{StateWriter, StateReader};
+
+// Bump when the serialized layout changes, and have read_state keep reading
+// the older layouts so nobody loses their progress.
+const SAVE_VERSION: u32 = 2; // 2 added bindings, look and view settings, and replayed sticks, countdowns and look scales
+
+// The best run of a level.
+#[derive(Clone)]
+pub struct Record {
+    pub time: f32, // ms
+    pub medal: Medal, // the best earned, even if the times for it change later
+    pub replay: Option<Replay>, // None if the run couldn't be recorded
+}
+
+// Everything that persists between sessions. Levels are keyed by their
+// catalog ids, which stay the same when levels are added or reordered.
+pub struct SaveData {
+    records: BTreeMap<String, Record>,
+    unlocked: BTreeSet<String>,
+    pub settings: Settings,
+    pub bindings: Bindings,
+}
+
+impl Default for SaveData {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SaveData {
+    pub fn new() -> Self {
+        Self {
+            records: BTreeMap::new(),
+            unlocked: BTreeSet::new(),
+            settings: Settings::new(),
+            bindings: Bindings::defaults(),
+        }
+    }
+
+    pub fn record(&self, id: &str) -> Option<&Record> {
+        self.records.get(id)
+    }
+
+    // Keeps the run if it's the fastest yet, and returns whether it was.
+    pub fn finish(&mut self, id: &str, time: f32, medal: Medal, replay: Option<Replay>) -> bool {
+        match self.records.get_mut(id) {
+            Some(record) if record.time <= time => {
+                if medal > record.medal {
+                    record.medal = medal;
+                }
+                false
+            },
+            Some(record) => {
+                record.time = time;
+                record.replay = replay;
+                if medal > record.medal {
+                    record.medal = medal;
+                }
+                true
+            },
+            None => {
+                self.records.insert(id.to_string(), Record { time, medal, replay });
+                true
+            },
+        }
+    }
+
+    pub fn unlocked(&self, id: &str) -> bool {
+        self.unlocked.contains(id)
+    }
+
+    pub fn unlock(&mut self, id: &str) {
+        self.unlocked.insert(id.to_string());
+    }
+
+    // Whitespace separated values, led by "save <version>".
+    pub fn serialize(&self) -> String {
+        let mut writer = StateWriter::new();
+        writer.word("save");
+        writer.u32(SAVE_VERSION);
+        self.settings.write_state(&mut writer);
+        self.bindings.write_state(&mut writer);
+        writer.usize(self.records.len());
+        for (id, record) in &self.records {
+            writer.word(id);
+            writer.f32(record.time);
+            writer.u32(record.medal as u32);
+            writer.bool(record.replay.is_some());
+            if let Some(replay) = &record.replay {
+                replay.write_state(&mut writer);
+            }
+        }
+        writer.usize(self.unlocked.len());
+        for id in &self.unlocked {
+            writer.word(id);
+        }
+        writer.finish()
+    }
+
+    // None if the data is damaged, or from a newer version than this one.
+    pub fn deserialize(data: &str) -> Option<SaveData> {
+        let mut reader = StateReader::new(data);
+        if reader.word()? != "save" {
+            return None
+        }
+        let version = reader.u32()?;
+        if version == 0 || version > SAVE_VERSION {
+            return None
+        }
+        Self::read_state(&mut reader, version)
+    }
+
+    // Reads any version up to SAVE_VERSION, filling in what older ones lack.
+    fn read_state(reader: &mut StateReader, version: u32) -> Option<SaveData> {
+        let settings = Settings::read_state(reader, version)?;
+        let bindings = if version >= 2 { Bindings::read_state(reader)? } else { Bindings::defaults() };
+        let mut records = BTreeMap::new();
+        for _record in 0..reader.usize()? {
+            let id = reader.word()?.to_string();
+            let time = reader.f32()?;
+            let medal = Medal::from_u8(reader.u32()? as u8)?;
+            let replay = if reader.bool()? { Some(Replay::read_state(reader, version)?) } else { None };
+            // holding opposite directions used to stop at the first let go, so
+            // older replays wouldn't play back the same; the record stands
+            let replay = replay.filter(|_| version >= 2);
+            records.insert(id, Record { time, medal, replay });
+        }
+        let mut unlocked = BTreeSet::new();
+        for _id in 0..reader.usize()? {
+            unlocked.insert(reader.word()?.to_string());
+        }
+        Some(SaveData {
+            records,
+            unlocked,
+            settings,
+            bindings,
+        })
+    }
+}
+
+// The native build keeps its save in a file.
+#[cfg(not(target_arch = "wasm32"))]
+impl SaveData {
+    // None if there's no file yet, or it doesn't parse.
+    pub fn load(path: &std::path::Path) -> Option<SaveData> {
+        Self::deserialize(&std::fs::read_to_string(path).ok()?)
+    }
+
+    pub fn store(&self, path: &std::path::Path) -> std::io::Result<()> {
+        std::fs::write(path, self.serialize())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::master::Master;
+    use crate::universe::{Input, TICK_TIME};
+    use crate::catalog::Medal;
+    use crate::bindings::{Action, Control};
+    use crate::settings::MAX_FOV;
+
+    // Uneven frames, with input during the countdown as well as after go.
+    #[test]
+    fn recorded_run_plays_back_from_save_data() {
+        let mut master = Master::new();
+        let mut universe = master.start(0, 1).unwrap();
+        for frame in 0..400 {
+            match frame {
+                20 => universe.player_input(0, Input::GoForward),
+                230 => universe.player_input(0, Input::Jump),
+                260 => universe.player_input(0, Input::GoRight),
+                // the look keys turn with the settings of the time, not of playback
+                280 => {
+                    let mut settings = Settings::new();
+                    settings.sensitivity = 3.;
+                    universe.set_settings(&settings);
+                    universe.player_input(0, Input::LookLeft);
+                },
+                _ => (),
+            }
+            if frame % 9 == 0 {
+                universe.look_input(0, 4., -1.);
+            }
+            universe.update(0, if frame % 2 == 0 { TICK_TIME * 0.9 } else { TICK_TIME * 1.3 });
+        }
+        assert!(universe.run_time() > 0.);
+        master.finish(0, &universe);
+
+        let mut loaded = Master::new();
+        assert!(loaded.load_save_data(&master.save_data()));
+        let played = loaded.replay_record(0).unwrap();
+        assert_eq!(played.run_time(), universe.run_time());
+        assert_eq!(played.checksum(), universe.checksum());
+    }
+
+    #[test]
+    fn edited_settings_are_clamped() {
+        let data = "save 2 0 0 1 -2 1 NaN 1 500 1 0 0 0";
+        let settings = SaveData::deserialize(data).unwrap().settings;
+        assert_eq!(settings.sensitivity, 0.);
+        assert_eq!(settings.sensitivity_x, 1.);
+        assert_eq!(settings.sensitivity_y, 0.);
+        assert_eq!(settings.fov, MAX_FOV);
+    }
+
+    #[test]
+    fn version_1_saves_still_load() {
+        // settings, then one record with a two step replay, then unlocks
+        let data = "save 1 0 1 2 1 training-1 9580 2 1 2 16.5 1 0 0 0 0 1 1 17 0 1 training-2";
+        let save = SaveData::deserialize(data).unwrap();
+        assert!(save.settings.aim_assist);
+        assert_eq!(save.settings.local_players, 2);
+        assert_eq!(save.settings.fov, Settings::new().fov);
+        assert!(save.bindings.lookup(&Control::key("KeyW")).action == Action::MoveForward);
+        let record = save.record("training-1").unwrap();
+        assert_eq!(record.time, 9580.);
+        assert!(record.medal == Medal::Silver);
+        // from before opposite directions cancelled out, so dropped
+        assert!(record.replay.is_none());
+        assert!(save.unlocked("training-2"));
+
+        let data = save.serialize();
+        assert_eq!(SaveData::deserialize(&data).unwrap().serialize(), data);
+    }
+}
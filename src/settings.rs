@@ -0,0 +1,71 @@
+use super::snapshot::{StateWriter, StateReader};
+
+pub const MIN_FOV: f32 = 30.; // degrees
+pub const MAX_FOV: f32 = 110.;
+
+// Player preferences that last between sessions, kept in the save data.
+#[derive(Clone, Copy)]
+pub struct Settings {
+    pub practice: bool,
+    pub aim_assist: bool,
+    pub local_players: usize,
+
+    // mouse look, on top of the player's look speed
+    pub sensitivity: f32,
+    pub sensitivity_x: f32,
+    pub sensitivity_y: f32,
+    pub invert_y: bool, // the mouse, look stick and look keys alike
+
+    pub fov: f32, // vertical, in degrees
+    pub head_bob: bool,
+}
+
+impl Settings {
+    pub fn new() -> Self {
+        Self {
+            practice: false,
+            aim_assist: false,
+            local_players: 1,
+            sensitivity: 1.,
+            sensitivity_x: 1.,
+            sensitivity_y: 1.,
+            invert_y: false,
+            fov: 45.,
+            head_bob: true,
+        }
+    }
+
+    pub fn write_state(&self, writer: &mut StateWriter) {
+        writer.bool(self.practice);
+        writer.bool(self.aim_assist);
+        writer.usize(self.local_players);
+        writer.f32(self.sensitivity);
+        writer.f32(self.sensitivity_x);
+        writer.f32(self.sensitivity_y);
+        writer.bool(self.invert_y);
+        writer.f32(self.fov);
+        writer.bool(self.head_bob);
+    }
+
+    // `version` is the save data's, for settings added since
+    pub fn read_state(reader: &mut StateReader, version: u32) -> Option<Self> {
+        let mut settings = Self::new();
+        settings.practice = reader.bool()?;
+        settings.aim_assist = reader.bool()?;
+        settings.local_players = reader.usize()?;
+        if version >= 2 {
+            settings.sensitivity = reader.f32()?;
+            settings.sensitivity_x = reader.f32()?;
+            settings.sensitivity_y = reader.f32()?;
+            settings.invert_y = reader.bool()?;
+            settings.fov = reader.f32()?;
+            settings.head_bob = reader.bool()?;
+        }
+        // as the setters would have, in case the data was edited
+        settings.sensitivity = settings.sensitivity.max(0.);
+        settings.sensitivity_x = settings.sensitivity_x.max(0.);
+        settings.sensitivity_y = settings.sensitivity_y.max(0.);
+        settings.fov = if settings.fov.is_nan() { Settings::new().fov } else { settings.fov.clamp(MIN_FOV, MAX_FOV) };
+        Some(settings)
+    }
+}
@@ -0,0 +1,142 @@
+use super::player::Player;
+use super::utils::Vec3;
+
+// bump when the serialized layout changes
+const SNAPSHOT_VERSION: u32 = 2;
+
+// Everything in a universe that changes during a run. Level geometry is
+// static, so it isn't included.
+#[derive(Clone)]
+pub struct Snapshot {
+    pub time: f32,
+    pub players: Vec<Player>,
+    pub countdown: u32, // ticks before go
+    pub countdown_elapsed: f32,
+    pub won: bool,
+    pub paused: bool,
+}
+
+impl Snapshot {
+    // Whitespace separated values, led by "snapshot <version>".
+    pub fn serialize(&self) -> String {
+        let mut writer = StateWriter::new();
+        writer.word("snapshot");
+        writer.u32(SNAPSHOT_VERSION);
+        writer.f32(self.time);
+        writer.usize(self.players.len());
+        for player in &self.players {
+            player.write_state(&mut writer);
+        }
+        writer.u32(self.countdown);
+        writer.f32(self.countdown_elapsed);
+        writer.bool(self.won);
+        writer.bool(self.paused);
+        writer.finish()
+    }
+
+    pub fn deserialize(data: &str) -> Option<Snapshot> {
+        let mut reader = StateReader::new(data);
+        if reader.word()? != "snapshot" || reader.u32()? != SNAPSHOT_VERSION {
+            return None
+        }
+        let time = reader.f32()?;
+        let mut players = vec![];
+        for _player in 0..reader.usize()? {
+            players.push(Player::read_state(&mut reader)?);
+        }
+        Some(Snapshot {
+            time,
+            players,
+            countdown: reader.u32()?,
+            countdown_elapsed: reader.f32()?,
+            won: reader.bool()?,
+            paused: reader.bool()?,
+        })
+    }
+}
+
+pub struct StateWriter {
+    out: String,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self {
+            out: String::new(),
+        }
+    }
+
+    pub fn word(&mut self, word: &str) {
+        if !self.out.is_empty() {
+            self.out.push(' ');
+        }
+        self.out.push_str(word);
+    }
+
+    // f32's Display is the shortest string that parses back to the same value
+    pub fn f32(&mut self, value: f32) {
+        self.word(&value.to_string());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.word(&value.to_string());
+    }
+
+    pub fn usize(&mut self, value: usize) {
+        self.word(&value.to_string());
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.word(if value { "1" } else { "0" });
+    }
+
+    pub fn vec3(&mut self, value: &Vec3) {
+        self.f32(value.x);
+        self.f32(value.y);
+        self.f32(value.z);
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+}
+
+pub struct StateReader<'a> {
+    words: std::str::SplitWhitespace<'a>,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a str) -> Self {
+        Self {
+            words: data.split_whitespace(),
+        }
+    }
+
+    pub fn word(&mut self) -> Option<&'a str> {
+        self.words.next()
+    }
+
+    pub fn f32(&mut self) -> Option<f32> {
+        self.word()?.parse().ok()
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        self.word()?.parse().ok()
+    }
+
+    pub fn usize(&mut self) -> Option<usize> {
+        self.word()?.parse().ok()
+    }
+
+    pub fn bool(&mut self) -> Option<bool> {
+        match self.word()? {
+            "1" => Some(true),
+            "0" => Some(false),
+            _ => None,
+        }
+    }
+
+    pub fn vec3(&mut self) -> Option<Vec3> {
+        Some(Vec3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+}
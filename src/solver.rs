@@ -0,0 +1,304 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet};
+use std::f32::consts::PI;
+use super::level::Level;
+use super::universe::{Universe, Input, TICK_TIME};
+use super::netcode::InputFrame;
+use super::snapshot::Snapshot;
+use super::events::EventKind;
+use super::player::{LOOK_SPEED, MOVE_SPEED};
+use super::grapple::Hand;
+use super::utils::{AABB, Vec3};
+
+const ACTION_TICKS: u32 = 8; // how long each choice is held for
+const MAX_STATES: usize = 400_000; // give up after searching this many
+const CELL_SIZE: f32 = 0.75; // states closer than this count as the same
+// how much the distance left (in ticks of running) counts against the ticks
+// taken; over 1 finds a way sooner, at the cost of a slower one
+const GREED: f32 = 1.5;
+const GOAL_SAMPLES: usize = 4; // per axis, when checking the win block isn't buried
+
+// relative to the direction of the goal
+const HEADINGS: [f32; 8] = [0., PI / 4., -PI / 4., PI / 2., -PI / 2., 3. * PI / 4., -3. * PI / 4., PI];
+const CAST_PITCHES: [f32; 3] = [PI / 6., PI / 3., 4. * PI / 9.];
+
+#[derive(Clone, Copy)]
+enum Action {
+    Run(f32), // heading
+    Jump(f32),
+    Grapple(f32, f32), // heading, pitch: look there, cast and pull
+    Release(f32),
+}
+
+// A way through a level: the inputs for the one player, tick by tick.
+pub struct Solution {
+    pub replay: Vec<InputFrame>,
+    pub ticks: u32,
+    pub explored: usize, // states searched to find it
+}
+
+impl Solution {
+    // How long the replay takes, a fair target for the level's par.
+    pub fn par_time(&self) -> f32 {
+        self.ticks as f32 * TICK_TIME
+    }
+}
+
+// Why a level couldn't be solved.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Failure {
+    // the win block has no inside, or all of it is inside other blocks, so
+    // the only way to reach it is to touch its surface
+    BuriedGoal,
+    NoRoute, // nothing the search tried got there
+    // the route won in the search but not played back from the start, so
+    // something in the simulation isn't deterministic
+    ReplayMismatch,
+}
+
+struct Node {
+    snapshot: Snapshot,
+    tick: u32,
+    step: usize, // index into the search's steps, to rebuild the replay
+    cost: f32, // ticks so far plus the weighted estimate of those left
+}
+
+// BinaryHeap pops the largest, so the cheapest node orders highest.
+impl Ord for Node {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        self.cost == other.cost
+    }
+}
+
+impl Eq for Node {}
+
+// One action taken, and the step it was taken from.
+struct Step {
+    parent: Option<usize>,
+    frames: Vec<InputFrame>,
+}
+
+enum Outcome {
+    Won(u32), // tick
+    Lost,
+    Moved(Node),
+}
+
+// Searches for a way from the level's start to its win block, stepping the
+// real player physics, and returns the first route it finds. That's quick
+// but not always the quickest, so its time is an estimate of par. NoRoute
+// usually means the level can't be beaten.
+pub fn solve(level: &Level) -> Result<Solution, Failure> {
+    if !goal_open(level) {
+        return Err(Failure::BuriedGoal)
+    }
+    let mut universe = Universe::new(level, 1);
+    let goal = level.win_block();
+    let goal_center = goal.origin + goal.dims / 2.;
+    let floor = level.blocks().iter().map(|block| block.min().y).fold(f32::INFINITY, f32::min);
+
+    let mut steps = vec![Step { parent: None, frames: vec![] }];
+    let mut queue = BinaryHeap::new();
+    queue.push(Node { snapshot: universe.snapshot(), tick: 0, step: 0, cost: 0. });
+    let mut visited = HashSet::new();
+    let mut explored = 0;
+
+    while let Some(node) = queue.pop() {
+        if explored >= MAX_STATES {
+            break
+        }
+        universe.restore(&node.snapshot);
+        let player = &universe.players()[0];
+        let to_goal = goal_center - player.position;
+        let goal_heading = to_goal.x.atan2(to_goal.z);
+        let has_grapple = player.grapple(Hand::Left).is_some();
+
+        for action in actions(goal_heading, has_grapple) {
+            explored += 1;
+            universe.restore(&node.snapshot);
+            let mut frames = vec![];
+            match run_action(&mut universe, action, node.tick, floor, &mut frames) {
+                Outcome::Lost => {},
+                Outcome::Won(tick) => {
+                    steps.push(Step { parent: Some(node.step), frames });
+                    let replay = rebuild(&steps, steps.len() - 1);
+                    // only trust what plays back the same from a clean start
+                    return match replay_ticks(level, &replay) {
+                        Some(ticks) if ticks == tick => Ok(Solution { replay, ticks, explored }),
+                        _ => Err(Failure::ReplayMismatch),
+                    }
+                },
+                Outcome::Moved(mut child) => {
+                    let player = &universe.players()[0];
+                    let cell = (
+                        (player.position.x / CELL_SIZE).floor() as i32,
+                        (player.position.y / CELL_SIZE).floor() as i32,
+                        (player.position.z / CELL_SIZE).floor() as i32,
+                        player.grapple(Hand::Left).map_or(0, |grapple| if grapple.hooked { 2 } else { 1 }),
+                    );
+                    if visited.insert(cell) {
+                        steps.push(Step { parent: Some(node.step), frames });
+                        child.step = steps.len() - 1;
+                        child.cost = child.tick as f32 + GREED * goal.distance_to(&player.position) / MOVE_SPEED;
+                        queue.push(child);
+                    }
+                },
+            }
+        }
+    }
+    Err(Failure::NoRoute)
+}
+
+// Whether some of the win block, with volume, is outside every other block.
+// Checked at points spread through it.
+fn goal_open(level: &Level) -> bool {
+    let goal = level.win_block();
+    if goal.dims.x <= 0. || goal.dims.y <= 0. || goal.dims.z <= 0. {
+        return false
+    }
+    let blocks = level.blocks();
+    let spread = |index: usize| (index as f32 + 0.5) / GOAL_SAMPLES as f32;
+    for x in 0..GOAL_SAMPLES {
+        for y in 0..GOAL_SAMPLES {
+            for z in 0..GOAL_SAMPLES {
+                let point = goal.origin + Vec3::new(goal.dims.x * spread(x), goal.dims.y * spread(y), goal.dims.z * spread(z));
+                if !blocks.iter().any(|block| block.contains(&point)) {
+                    return true
+                }
+            }
+        }
+    }
+    false
+}
+
+// Plays a replay from the level's start, returning the tick it wins on.
+pub fn replay_ticks(level: &Level, replay: &[InputFrame]) -> Option<u32> {
+    let mut universe = Universe::new(level, 1);
+    for frame in replay {
+        frame.apply(&mut universe);
+        universe.step(TICK_TIME);
+        if universe.winner().is_some() {
+            return Some(frame.tick + 1)
+        }
+    }
+    None
+}
+
+fn actions(goal_heading: f32, has_grapple: bool) -> Vec<Action> {
+    let mut actions = vec![];
+    for offset in HEADINGS.iter() {
+        let heading = goal_heading + offset;
+        actions.push(Action::Run(heading));
+        actions.push(Action::Jump(heading));
+        if has_grapple {
+            actions.push(Action::Release(heading));
+        } else {
+            for pitch in CAST_PITCHES.iter() {
+                actions.push(Action::Grapple(heading, *pitch));
+            }
+        }
+    }
+    actions
+}
+
+// Holds an action for ACTION_TICKS, recording the frames that did it.
+fn run_action(universe: &mut Universe, action: Action, start_tick: u32, floor: f32, frames: &mut Vec<InputFrame>) -> Outcome {
+    let (heading, pitch) = match action {
+        Action::Run(heading) | Action::Jump(heading) | Action::Release(heading) => (heading, 0.),
+        Action::Grapple(heading, pitch) => (heading, pitch),
+    };
+    universe.take_events();
+
+    for offset in 0..ACTION_TICKS {
+        let tick = start_tick + offset;
+        let mut frame = InputFrame::empty(tick, 0);
+        if offset == 0 {
+            let player = &universe.players()[0];
+            // mouse_look turns by the movement times LOOK_SPEED, with y inverted
+            frame.look = ((heading - player.theta()) / LOOK_SPEED, -(pitch - player.phi()) / LOOK_SPEED);
+            frame.inputs.push(Input::GoForward);
+            match action {
+                Action::Jump(_) => frame.inputs.push(Input::Jump),
+                // cast toggles an existing grapple off
+                Action::Release(_) => frame.inputs.extend_from_slice(&[Input::Release, Input::Cast]),
+                _ => {},
+            }
+        } else if offset == 1 {
+            // a tick after turning, so the cast goes where the player now looks
+            if let Action::Grapple(..) = action {
+                frame.inputs.extend_from_slice(&[Input::Cast, Input::Pull]);
+            }
+        }
+        frame.apply(universe);
+        universe.step(TICK_TIME);
+        let won = universe.winner().is_some();
+        frames.push(frame);
+        if won {
+            return Outcome::Won(tick + 1)
+        }
+        if universe.players()[0].position.y < floor {
+            return Outcome::Lost
+        }
+    }
+
+    for index in 0..universe.take_events() {
+        if universe.event(index).kind == EventKind::Died {
+            return Outcome::Lost
+        }
+    }
+    Outcome::Moved(Node { snapshot: universe.snapshot(), tick: start_tick + ACTION_TICKS, step: 0, cost: 0. })
+}
+
+fn rebuild(steps: &[Step], last: usize) -> Vec<InputFrame> {
+    let mut chain = vec![];
+    let mut step = Some(last);
+    while let Some(index) = step {
+        chain.push(index);
+        step = steps[index].parent;
+    }
+    chain.iter().rev().flat_map(|index| steps[*index].frames.iter().cloned()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::palette::Palette;
+    use crate::rules::Rules;
+
+    // A floor with the win block standing on it a short run ahead.
+    fn fixture(win_block: Block) -> Level {
+        let floor = vec![-10., -1., -10., 20., 1., 30., 0.];
+        Level::new(floor, win_block, Vec3::new(0., 1.5, 0.), Palette::classic(), Rules::non_contact(), vec![])
+    }
+
+    #[test]
+    fn solution_replays_to_the_same_win() {
+        let level = fixture(Block::new(Vec3::new(-1.5, 0., 8.), Vec3::new(3., 2., 2.), 0));
+        let solution = match solve(&level) {
+            Ok(solution) => solution,
+            Err(failure) => panic!("a short run on flat ground failed: {:?}", failure),
+        };
+        assert_eq!(replay_ticks(&level, &solution.replay), Some(solution.ticks));
+    }
+
+    #[test]
+    fn goals_without_room_inside_are_rejected() {
+        let flat = fixture(Block::new(Vec3::new(-1.5, 0., 8.), Vec3::new(3., 0., 2.), 0));
+        assert_eq!(solve(&flat).err(), Some(Failure::BuriedGoal));
+        let buried = fixture(Block::new(Vec3::new(-1.5, -1., 8.), Vec3::new(3., 1., 2.), 0));
+        assert_eq!(solve(&buried).err(), Some(Failure::BuriedGoal));
+    }
+}
@@ -1,13 +1,41 @@
 use wasm_bindgen::prelude::*;
 use super::utils::{Vec3, AABB};
-use super::player::{Player, Go};
+use super::player::{Player, Go, MOVE_SPEED};
 
 use super::block::Block;
-use super::graphics::Graphics;
+use super::graphics::{Graphics, graphics_accessors};
+use super::level::Level;
+use super::palette::Palette;
+use super::rules::{Rules, PlayerGrapple};
+use super::snapshot::Snapshot;
+use super::events::{Event, EventKind};
+use super::hud::{Hud, GrappleStatus, Aim};
+use super::grapple::{self, Grapple, Hand, GRAPPLE_LENGTH};
+use super::replay::{Replay, ReplayStep};
+use super::netcode::InputFrame;
+use super::bindings::Action;
+use super::input::{Stick, deadzone};
+use super::settings::{Settings, MIN_FOV, MAX_FOV};
 
 use super::log;
 
+// milliseconds simulated per tick when running at a fixed rate
+pub const TICK_TIME: f32 = 1000. / 60.;
+
+pub const MAX_LOCAL_PLAYERS: usize = 4;
+
+pub const GRAVITY: f32 = -0.01; // per tick
+
+pub const PRACTICE_SLOTS: usize = 4;
+
+const RESUME_TIME: f32 = 3000.; // ms counted down before a paused run carries on
+
+// the camera bobs this high and this often running at full speed on the ground
+const BOB_HEIGHT: f32 = 0.04;
+const BOB_TIME: f32 = 350.; // ms
+
 #[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq)]
 pub enum Input {
     GoLeft = 0,
     GoForward = 1,
@@ -21,248 +49,1111 @@ pub enum Input {
     Cast = 9,
     Pull = 10,
     Release = 11,
+    CastRight = 12,
+    PullRight = 13,
+    ReleaseRight = 14,
+    LookLeft = 15,
+    LookRight = 16,
+    LookUp = 17,
+    LookDown = 18,
+    StopLookLeft = 19,
+    StopLookRight = 20,
+    StopLookUp = 21,
+    StopLookDown = 22,
 }
 
 #[wasm_bindgen]
 pub struct Universe {
     players: Vec<Player>,
+    local_players: usize, // the first players, each with their own viewport
 
     start_pos: Vec3,
+    time: f32, // milliseconds simulated since the run started
 
     win_block: Block,
     
     gravity: f32, // negative
 
     blocks: Vec<Block>,
+    checkpoints: Vec<Block>,
+    kill_height: f32, // players falling below this die
 
+    palette: Palette,
+    rules: Rules,
     graphics: Graphics,
+
+    // practice runs can save and load state, so they don't count for records
+    practice: bool,
+    save_slots: Vec<Option<Snapshot>>,
+    time_scale: f32,
+    frame_advance: bool, // when set, only step when a frame is requested
+    pending_frames: u32,
+
+    aim_assist: bool,
+    assisted: bool, // aim assist was on at some point this run
+    settings: Settings, // how the players look around and see
+
+    events: Vec<Event>,
+    drained_events: Vec<Event>, // what JS reads after take_events
+    won: bool,
+
+    recording: Option<Replay>, // None when not recording
+    recorded: Vec<InputFrame>, // inputs since the last step
+
+    paused: bool,
+    resume_countdown: f32, // ms left before a resumed run steps again
+
+    // whole ticks before go, so every peer starts on the same one
+    countdown: u32,
+    countdown_elapsed: f32, // ms toward the next of those ticks
+    countdown_ticks: u32, // what restarts count down from
 }
 
-impl Universe {
-    pub fn new(block_data: Vec<f32>, win_block: Block, start_pos: Vec3) -> Self {
-        let mut count = 0;
-        let mut data = vec![];
-        let mut new_data: [f32; 6] = [0.; 6];
-        for i in &block_data {
-            new_data[count] = *i;
-            count += 1;
-            if count == 6 {
-                data.push(new_data);
-                new_data = [0.; 6];
-                count = 0;
-            }
+impl Input {
+    pub fn from_u8(code: u8) -> Option<Input> {
+        match code {
+            0 => Some(Input::GoLeft),
+            1 => Some(Input::GoForward),
+            2 => Some(Input::GoRight),
+            3 => Some(Input::GoBack),
+            4 => Some(Input::StopLeft),
+            5 => Some(Input::StopForward),
+            6 => Some(Input::StopRight),
+            7 => Some(Input::StopBack),
+            8 => Some(Input::Jump),
+            9 => Some(Input::Cast),
+            10 => Some(Input::Pull),
+            11 => Some(Input::Release),
+            12 => Some(Input::CastRight),
+            13 => Some(Input::PullRight),
+            14 => Some(Input::ReleaseRight),
+            15 => Some(Input::LookLeft),
+            16 => Some(Input::LookRight),
+            17 => Some(Input::LookUp),
+            18 => Some(Input::LookDown),
+            19 => Some(Input::StopLookLeft),
+            20 => Some(Input::StopLookRight),
+            21 => Some(Input::StopLookUp),
+            22 => Some(Input::StopLookDown),
+            _ => None,
         }
+    }
+}
 
-        let mut blocks = vec![];
-        for block in &data {
-            let origin = Vec3::new(block[0], block[1], block[2]);
-            let dims = Vec3::new(block[3], block[4], block[5]);
-            blocks.push(Block::new(origin, dims));
-        }
+impl Universe {
+    pub fn new(level: &Level, local_players: usize) -> Self {
+        let local_players = local_players.clamp(1, MAX_LOCAL_PLAYERS);
+        let start_pos = level.start_pos();
+
+        let blocks = level.blocks();
+
+        let mut graphics = Graphics::new();
+        graphics.set_light(Vec3::new(-0.4, -1., 0.3), 0.45);
+        graphics.set_views(local_players);
+
+        let lowest = blocks.iter().map(|block| block.origin.y).fold(start_pos.y, f32::min);
 
         Self {
-            players: vec![Player::new(start_pos)],//, Player::new()],
+            players: (0..local_players).map(|index| Player::new(Self::spawn_point(start_pos, index))).collect(),
+            local_players,
             start_pos,
-            win_block,
-            gravity: -0.01,
+            time: 0.,
+            win_block: level.win_block(),
+            gravity: GRAVITY,
             blocks,
-            graphics: Graphics::new(),
+            checkpoints: level.checkpoints(),
+            kill_height: lowest - 30.,
+            palette: level.palette(),
+            rules: level.rules(),
+            graphics,
+            practice: false,
+            save_slots: vec![None; PRACTICE_SLOTS],
+            time_scale: 1.,
+            frame_advance: false,
+            pending_frames: 0,
+            aim_assist: false,
+            assisted: false,
+            settings: Settings::new(),
+            events: vec![],
+            drained_events: vec![],
+            won: false,
+            recording: None,
+            recorded: vec![],
+            paused: false,
+            resume_countdown: 0.,
+            countdown: 0,
+            countdown_elapsed: 0.,
+            countdown_ticks: 0,
+        }
+    }
+
+    // Players line up side by side so they don't spawn inside each other.
+    fn spawn_point(start_pos: Vec3, index: usize) -> Vec3 {
+        start_pos + Vec3::new(index as f32 * 1.5, 0., 0.)
+    }
+
+    fn camera(&self, player: usize) -> (Vec3, f32, f32) {
+        let player = &self.players[player];
+        let mut eye = player.position + Vec3::new(0., player.dims.y / 25., 0.);
+        // only the view bobs, never where the player is or aims from
+        if self.settings.head_bob && player.on_ground() {
+            let speed = Vec3::new(player.velocity.x, 0., player.velocity.z).length();
+            let phase = self.time / BOB_TIME * std::f32::consts::PI * 2.;
+            eye.y += phase.sin() * BOB_HEIGHT * (speed / MOVE_SPEED).min(1.);
+        }
+        (eye, player.theta(), -player.phi())
+    }
+
+    // Steps physics only, so netcode can run ticks without building meshes.
+    pub fn step(&mut self, elapsed_time: f32) {
+        // players are held at spawn until go, and the clock starts then
+        if self.countdown > 0 {
+            self.countdown_elapsed += elapsed_time;
+            while self.countdown > 0 && self.countdown_elapsed >= TICK_TIME {
+                self.countdown_elapsed -= TICK_TIME;
+                self.countdown -= 1;
+            }
+            if self.countdown == 0 {
+                self.countdown_elapsed = 0.;
+                self.events.push(Event::new(EventKind::Started));
+            }
+            for player in &mut self.players {
+                player.look_around(elapsed_time / TICK_TIME);
+            }
+            self.record_step(elapsed_time);
+            return
+        }
+
+        let player_boxes: Vec<Block> = if self.rules.player_collision {
+            self.players.iter().map(|player| Block::new(player.min(), player.dims, 0)).collect()
+        } else {
+            vec![]
+        };
+        for (index, player) in self.players.iter_mut().enumerate() {
+            let others: Vec<Block> = player_boxes.iter().enumerate()
+                .filter(|(other, _)| *other != index)
+                .map(|(_, block)| block.clone())
+                .collect();
+            player.update(&self.blocks, &others, self.gravity, elapsed_time);
+        }
+        self.update_player_grapples(elapsed_time / TICK_TIME);
+        self.update_progress();
+        self.time += elapsed_time;
+        self.record_step(elapsed_time);
+    }
+
+    fn record_step(&mut self, elapsed_time: f32) {
+        if let Some(recording) = &mut self.recording {
+            let frames = std::mem::take(&mut self.recorded);
+            recording.steps.push(ReplayStep { frames, elapsed: elapsed_time });
+        }
+    }
+
+    // Holds players at spawn for this many ticks before the run starts, now
+    // and on every restart. A recording starts over, counting down the same.
+    pub fn set_countdown(&mut self, ticks: u32) {
+        self.countdown_ticks = ticks;
+        self.countdown = ticks;
+        self.countdown_elapsed = 0.;
+        self.restart_recording();
+    }
+
+    // The run so far, if it's being recorded.
+    pub fn replay(&self) -> Option<&Replay> {
+        self.recording.as_ref()
+    }
+
+    fn record_input(&mut self, player: usize, input: Input) {
+        if self.recording.is_none() {
+            return
+        }
+        // frames apply inputs before look, so input after look starts a new one
+        match self.recorded.last_mut() {
+            Some(frame) if frame.player as usize == player && frame.look == (0., 0.) => frame.inputs.push(input),
+            _ => {
+                let mut frame = InputFrame::empty(self.recorded_ticks(), player as u8);
+                frame.inputs.push(input);
+                self.recorded.push(frame);
+            },
+        }
+    }
+
+    fn record_look(&mut self, player: usize, movement_x: f32, movement_y: f32) {
+        if self.recording.is_none() {
+            return
+        }
+        // never summed, as that wouldn't round the same as turning twice
+        match self.recorded.last_mut() {
+            Some(frame) if frame.player as usize == player && frame.look == (0., 0.) => frame.look = (movement_x, movement_y),
+            _ => {
+                let mut frame = InputFrame::empty(self.recorded_ticks(), player as u8);
+                frame.look = (movement_x, movement_y);
+                self.recorded.push(frame);
+            },
+        }
+    }
+
+    // Where a stick's been moved to, deadzone already taken out. Only
+    // changes are recorded, as a stick held still reads the same every poll.
+    pub fn stick_input(&mut self, curr_player: usize, stick: Stick, value: (f32, f32)) {
+        if self.players[curr_player].stick(stick) == value {
+            return
+        }
+        self.record_stick(curr_player, stick, value);
+        self.players[curr_player].set_stick(stick, value);
+    }
+
+    // Mouse movement as it turns the player, settings already applied.
+    pub fn look_input(&mut self, curr_player: usize, movement_x: f32, movement_y: f32) {
+        self.record_look(curr_player, movement_x, movement_y);
+        self.players[curr_player].mouse_look(movement_x, movement_y);
+    }
+
+    // The look sensitivity for the player's stick and keys. Recorded like
+    // input, as it changes how the player turns.
+    pub fn look_scale_input(&mut self, curr_player: usize, scale: (f32, f32)) {
+        if self.players[curr_player].look_scale() == scale {
+            return
+        }
+        self.record_look_scale(curr_player, scale);
+        self.players[curr_player].set_look_scale(scale);
+    }
+
+    fn record_look_scale(&mut self, player: usize, scale: (f32, f32)) {
+        if self.recording.is_none() {
+            return
+        }
+        match self.recorded.last_mut() {
+            Some(frame) if frame.player as usize == player && frame.look == (0., 0.) => frame.look_scale = Some(scale),
+            _ => {
+                let mut frame = InputFrame::empty(self.recorded_ticks(), player as u8);
+                frame.look_scale = Some(scale);
+                self.recorded.push(frame);
+            },
+        }
+    }
+
+    fn record_stick(&mut self, player: usize, stick: Stick, value: (f32, f32)) {
+        if self.recording.is_none() {
+            return
+        }
+        // sticks are positions rather than moves, so a later one can replace one
+        // from the same frame; frames apply them after inputs and before look
+        match self.recorded.last_mut() {
+            Some(frame) if frame.player as usize == player && frame.look == (0., 0.) => frame.set_stick(stick, value),
+            _ => {
+                let mut frame = InputFrame::empty(self.recorded_ticks(), player as u8);
+                frame.set_stick(stick, value);
+                self.recorded.push(frame);
+            },
+        }
+    }
+
+    fn recorded_ticks(&self) -> u32 {
+        self.recording.as_ref().map_or(0, |recording| recording.steps.len() as u32)
+    }
+
+    // Checkpoints, deaths and winning, and collecting everyone's events.
+    fn update_progress(&mut self) {
+        for (index, player) in self.players.iter_mut().enumerate() {
+            for (checkpoint, block) in self.checkpoints.iter().enumerate() {
+                if player.overlaps(block) && player.checkpoint.is_none_or(|reached| checkpoint > reached) {
+                    player.checkpoint = Some(checkpoint);
+                    player.events.push(Event::checkpoint(checkpoint));
+                }
+            }
+
+            if player.position.y < self.kill_height {
+                let respawn = match player.checkpoint {
+                    Some(checkpoint) => {
+                        let block = &self.checkpoints[checkpoint];
+                        block.origin + Vec3::new(block.dims.x / 2., player.dims.y / 2., block.dims.z / 2.)
+                    },
+                    None => Self::spawn_point(self.start_pos, index),
+                };
+                let mut events = std::mem::take(&mut player.events);
+                let checkpoint = player.checkpoint;
+                let look_scale = player.look_scale();
+                *player = Player::new(respawn);
+                player.checkpoint = checkpoint;
+                player.set_look_scale(look_scale);
+                events.push(Event::new(EventKind::Died));
+                player.events = events;
+            }
+
+            for event in player.events.drain(..) {
+                self.events.push(event.for_player(index));
+            }
+        }
+
+        if !self.won {
+            if let Some(winner) = self.winner() {
+                self.won = true;
+                self.events.push(Event::new(EventKind::LevelWon).for_player(winner));
+            }
+        }
+    }
+
+    // Hooks casting grapples onto other players, and keeps hooked ones attached.
+    fn update_player_grapples(&mut self, time_step: f32) {
+        let positions: Vec<Vec3> = self.players.iter().map(|player| player.position).collect();
+        let player_boxes: Vec<Block> = self.players.iter().map(|player| Block::new(player.min(), player.dims, 0)).collect();
+        let mut drags = vec![];
+
+        for (index, player) in self.players.iter_mut().enumerate() {
+            for hand in Hand::all().iter() {
+                let pulling = player.pulling(*hand);
+                let mut release = false;
+                if let Some(grapple) = &mut player.grapples[*hand as usize] {
+                    if self.rules.player_grapple == PlayerGrapple::Off {
+                        release = grapple.target.is_some();
+                    } else if !grapple.hooked {
+                        for (other, player_box) in player_boxes.iter().enumerate() {
+                            if other != index && player_box.contains(&grapple.end) {
+                                grapple.hook(&positions[index]);
+                                grapple.target = Some(other);
+                                grapple.pulls_target = self.rules.player_grapple == PlayerGrapple::PullThem;
+                                player.events.push(Event::hooked(grapple.end).for_hand(*hand));
+                                log("Hooked player!");
+                                break;
+                            }
+                        }
+                    }
+                    if let Some(target) = grapple.target {
+                        grapple.end = positions[target];
+                        if pulling && grapple.pulls_target {
+                            drags.push((target, (positions[index] - positions[target]).unit() * grapple.pull * time_step));
+                        }
+                    }
+                }
+                if release {
+                    player.grapples[*hand as usize] = None;
+                    player.events.push(Event::new(EventKind::GrappleBroke).for_hand(*hand));
+                }
+            }
+        }
+
+        for (target, drag) in drags {
+            self.players[target].velocity += drag;
+        }
+    }
+
+    // The direction a cast would go, after aim assist.
+    fn cast_dir(&self, player: usize) -> Vec3 {
+        let player = &self.players[player];
+        let look_dir = player.look_dir();
+        if !self.aim_assist {
+            return look_dir
+        }
+        grapple::assist(&self.blocks, &player.position, &look_dir).unwrap_or(look_dir)
+    }
+
+    pub fn players(&self) -> &Vec<Player> {
+        &self.players
+    }
+
+    // The look and view settings, applied from now on, mid-run included.
+    // Practice and aim assist change what a run counts for, so they're set
+    // on their own.
+    pub fn set_settings(&mut self, settings: &Settings) {
+        self.settings = *settings;
+        self.graphics.set_fov(settings.fov.clamp(MIN_FOV, MAX_FOV).to_radians());
+        let scale = (settings.sensitivity * settings.sensitivity_x, settings.sensitivity * settings.sensitivity_y);
+        for player in 0..self.local_players.min(self.players.len()) {
+            self.look_scale_input(player, scale);
+        }
+    }
+
+    pub fn set_practice(&mut self, practice: bool) {
+        self.practice = practice;
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            time: self.time,
+            players: self.players.clone(),
+            countdown: self.countdown,
+            countdown_elapsed: self.countdown_elapsed,
+            won: self.won,
+            paused: self.paused,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.time = snapshot.time;
+        self.players = snapshot.players.clone();
+        self.countdown = snapshot.countdown;
+        self.countdown_elapsed = snapshot.countdown_elapsed;
+        self.won = snapshot.won;
+        self.paused = snapshot.paused;
+        self.resume_countdown = 0.;
+        // what happened after the snapshot hasn't happened any more
+        self.events.clear();
+    }
+
+    // Events not yet taken, for netcode to put back after resimulating ticks
+    // whose events were already reported.
+    pub fn pending_events(&self) -> Vec<Event> {
+        self.events.clone()
+    }
+
+    pub fn set_pending_events(&mut self, events: Vec<Event>) {
+        self.events = events;
+    }
+
+    // FNV-1a over the bits of everything that evolves during a run
+    pub fn checksum_players(players: &[Player]) -> u32 {
+        let mut hash: u32 = 0x811c9dc5;
+        let mut add = |value: f32| {
+            for byte in &value.to_bits().to_le_bytes() {
+                hash ^= *byte as u32;
+                hash = hash.wrapping_mul(0x01000193);
+            }
+        };
+        for player in players {
+            for v in &[player.position, player.velocity] {
+                add(v.x);
+                add(v.y);
+                add(v.z);
+            }
+            add(player.theta());
+            add(player.phi());
+            add(player.look_scale().0);
+            add(player.look_scale().1);
+            for value in player.input().values() {
+                add(value);
+            }
+            add(if player.on_ground() { 1. } else { 0. });
+            for hand in Hand::all().iter() {
+                add(if player.pulling(*hand) { 1. } else { 0. });
+            }
+            for grapple in player.grapples.iter().flatten() {
+                add(grapple.end.x);
+                add(grapple.end.y);
+                add(grapple.end.z);
+                add(if grapple.hooked { 1. } else { 0. });
+                add(grapple.rope_length);
+                for pivot in &grapple.pivots {
+                    add(pivot.point.x);
+                    add(pivot.point.y);
+                    add(pivot.point.z);
+                }
+            }
         }
+        hash
     }
 }
 
 #[wasm_bindgen]
 impl Universe {
     pub fn won_level(&self) -> bool {
-        (self.players[0].collision(&self.win_block, &self.players[0].velocity)).length() > 0.
+        self.winner().is_some()
+    }
+
+    // The first player touching the win block, if any.
+    pub fn winner(&self) -> Option<usize> {
+        self.players.iter().position(|player| player.collision(&self.win_block, &player.velocity).length() > 0.)
     }
 
     pub fn restart(&mut self) {
-        self.players[0] = Player::new(self.start_pos);
-    } 
+        for (index, player) in self.players.iter_mut().enumerate() {
+            let look_scale = player.look_scale();
+            *player = Player::new(Self::spawn_point(self.start_pos, index));
+            player.set_look_scale(look_scale);
+        }
+        self.time = 0.;
+        self.won = false;
+        self.events.clear();
+        self.paused = false;
+        self.resume_countdown = 0.;
+        self.countdown = self.countdown_ticks;
+        self.countdown_elapsed = 0.;
+        self.assisted = self.aim_assist;
+        self.restart_recording();
+    }
+
+    // A replay plays back into fresh players, so a new recording starts by
+    // setting the look scales they've been given since.
+    fn restart_recording(&mut self) {
+        if self.recording.is_none() {
+            return
+        }
+        self.recording = Some(Replay::new(self.countdown));
+        self.recorded.clear();
+        for player in 0..self.players.len() {
+            let scale = self.players[player].look_scale();
+            if scale != (1., 1.) {
+                self.record_look_scale(player, scale);
+            }
+        }
+    }
+
+    // Records the run's inputs from here on, for Replay::play. Turn it on
+    // before the run starts for a replay of the whole thing.
+    pub fn set_recording(&mut self, enabled: bool) {
+        self.recording = if enabled { Some(Replay::new(self.countdown)) } else { None };
+        self.recorded.clear();
+    }
+
+    // Moves the events since the last call where event() can read them,
+    // and returns how many there are.
+    pub fn take_events(&mut self) -> usize {
+        self.drained_events.clear();
+        self.drained_events.append(&mut self.events);
+        self.drained_events.len()
+    }
+
+    pub fn event(&self, index: usize) -> Event {
+        self.drained_events[index]
+    }
+
+    // Call after update or draw for the same view, so the aspect matches.
+    pub fn hud(&mut self, curr_player: usize) -> Hud {
+        let (cam_pos, cam_theta, cam_phi) = self.camera(curr_player);
+        self.graphics.set_camera(cam_pos, cam_theta, cam_phi);
+        let player = &self.players[curr_player];
+
+        let grapple_status = |grapple: Option<&Grapple>| match grapple {
+            None => (GrappleStatus::Ready, 0.),
+            Some(grapple) => {
+                let status = if grapple.target.is_some() {
+                    GrappleStatus::HookedPlayer
+                } else if grapple.hooked {
+                    GrappleStatus::Hooked
+                } else {
+                    GrappleStatus::Casting
+                };
+                (status, (grapple.end - player.position).length())
+            },
+        };
+        let (left_grapple, left_grapple_distance) = grapple_status(player.grapple(Hand::Left));
+        let (right_grapple, right_grapple_distance) = grapple_status(player.grapple(Hand::Right));
+
+        let goal = self.win_block.origin + self.win_block.dims / 2.;
+        let (x, y, in_front) = self.graphics.project(&goal);
+        let goal_on_screen = in_front && x.abs() <= 1. && y.abs() <= 1.;
+        // straight behind has no direction on screen, so point down
+        let (goal_x, goal_y) = if goal_on_screen { (x, y) } else if x == 0. && y == 0. { (0., -1.) } else {
+            let length = (x * x + y * y).sqrt();
+            (x / length, y / length)
+        };
+
+        Hud {
+            speed: player.velocity.length() * 1000. / TICK_TIME,
+            left_grapple,
+            left_grapple_distance,
+            right_grapple,
+            right_grapple_distance,
+            grapple_length: GRAPPLE_LENGTH,
+            time: self.time,
+            countdown: self.resume_countdown.max(self.countdown as f32 * TICK_TIME - self.countdown_elapsed),
+            checkpoint: player.checkpoint,
+            checkpoints: self.checkpoints.len(),
+            goal_x,
+            goal_y,
+            goal_on_screen,
+            goal_distance: (goal - player.position).length(),
+        }
+    }
+
+    pub fn aim(&self, curr_player: usize) -> Aim {
+        let player = &self.players[curr_player];
+        let cast_dir = self.cast_dir(curr_player);
+        match grapple::aim(&self.blocks, &player.position, &cast_dir) {
+            Some(point) => Aim {
+                hit: true,
+                x: point.x,
+                y: point.y,
+                z: point.z,
+                distance: (point - player.position).length(),
+                assisted: self.aim_assist && grapple::aim(&self.blocks, &player.position, &player.look_dir()).is_none(),
+            },
+            None => Aim { hit: false, x: 0., y: 0., z: 0., distance: 0., assisted: false },
+        }
+    }
+
+    // Part of the simulation, so every peer in a net session needs the same setting.
+    pub fn set_aim_assist(&mut self, enabled: bool) {
+        // a replay plays back with one setting throughout, so changing it
+        // mid-run stops the recording
+        if enabled != self.aim_assist && self.time > 0. {
+            self.recording = None;
+        }
+        self.aim_assist = enabled;
+        self.assisted |= enabled;
+    }
+
+    pub fn aim_assist(&self) -> bool {
+        self.aim_assist
+    }
+
+    pub fn run_time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn save_snapshot(&self) -> String {
+        self.snapshot().serialize()
+    }
+
+    // Returns false and leaves the universe untouched if the data doesn't
+    // parse or was taken with a different number of players.
+    pub fn load_snapshot(&mut self, data: &str) -> bool {
+        match Snapshot::deserialize(data) {
+            Some(snapshot) if snapshot.players.len() == self.players.len() => {
+                self.restore(&snapshot);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    // Stops the run where it is, timer and all. Held movement, looks and
+    // pulls are let go, and sticks centered, as their key ups may never
+    // arrive while paused. Grapples stay hooked, so resuming mid-swing
+    // carries on swinging.
+    pub fn pause(&mut self) {
+        for player in 0..self.players.len() {
+            let stops = [
+                Input::StopLeft, Input::StopForward, Input::StopRight, Input::StopBack,
+                Input::StopLookLeft, Input::StopLookRight, Input::StopLookUp, Input::StopLookDown,
+                Input::Release, Input::ReleaseRight, // only stop pulling
+            ];
+            for input in stops.iter() {
+                self.player_input(player, *input);
+            }
+            self.stick_input(player, Stick::Move, (0., 0.));
+            self.stick_input(player, Stick::Look, (0., 0.));
+        }
+        self.paused = true;
+        self.resume_countdown = 0.;
+    }
+
+    // Carries on after a countdown of RESUME_TIME.
+    pub fn resume(&mut self) {
+        if self.paused {
+            self.paused = false;
+            self.resume_countdown = RESUME_TIME;
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn counting_down(&self) -> bool {
+        self.countdown > 0
+    }
 
     pub fn update(&mut self, curr_player: usize, elapsed_time: f32) {
-        for player in &mut self.players {
-            player.update(&self.blocks, self.gravity, elapsed_time);
+        if self.paused {
+            // frozen, just drawn
+        } else if self.resume_countdown > 0. {
+            // in real time, so slow motion doesn't drag it out
+            self.resume_countdown = (self.resume_countdown - elapsed_time).max(0.);
+        } else if !self.frame_advance {
+            self.step(elapsed_time * self.time_scale);
+        } else if self.pending_frames > 0 {
+            self.step(TICK_TIME);
+            self.pending_frames -= 1;
+        }
+        self.update_graphics(curr_player);
+    }
+
+    pub fn practice(&self) -> bool {
+        self.practice
+    }
+
+    // Runs in practice, or with aim assist on at any point, don't set records.
+    pub fn counts_for_records(&self) -> bool {
+        !self.practice && !self.assisted
+    }
+
+    pub fn save_state(&mut self, slot: usize) {
+        if self.practice && slot < PRACTICE_SLOTS {
+            self.save_slots[slot] = Some(self.snapshot());
+        }
+    }
+
+    // Returns whether there was a state in the slot to load.
+    pub fn load_state(&mut self, slot: usize) -> bool {
+        if !self.practice || slot >= PRACTICE_SLOTS {
+            return false
+        }
+        match self.save_slots[slot].clone() {
+            Some(snapshot) => {
+                self.restore(&snapshot);
+                true
+            },
+            None => false,
         }
+    }
+
+    pub fn set_time_scale(&mut self, time_scale: f32) {
+        if self.practice && !time_scale.is_nan() {
+            self.time_scale = time_scale.clamp(0.05, 1.);
+        }
+    }
+
+    pub fn time_scale(&self) -> f32 {
+        self.time_scale
+    }
+
+    pub fn set_frame_advance(&mut self, enabled: bool) {
+        if self.practice {
+            self.frame_advance = enabled;
+            self.pending_frames = 0;
+        }
+    }
+
+    pub fn frame_advance(&self) -> bool {
+        self.frame_advance
+    }
+
+    // Steps a single tick on the next update while frame advance is on.
+    pub fn advance_frame(&mut self) {
+        if self.frame_advance {
+            self.pending_frames += 1;
+        }
+    }
+
+    // Rebuilds graphics without stepping, for when netcode drives the ticks.
+    pub fn draw(&mut self, curr_player: usize) {
         self.update_graphics(curr_player);
     }
 
+    pub fn add_player(&mut self) -> usize {
+        self.players.push(Player::new(Self::spawn_point(self.start_pos, self.players.len())));
+        self.players.len() - 1
+    }
+
+    pub fn local_players(&self) -> usize {
+        self.local_players
+    }
+
+    pub fn num_players(&self) -> usize {
+        self.players.len()
+    }
+
+    pub fn set_player_collision(&mut self, enabled: bool) {
+        self.rules.player_collision = enabled;
+    }
+
+    pub fn set_player_grapple(&mut self, player_grapple: PlayerGrapple) {
+        self.rules.player_grapple = player_grapple;
+    }
+
+    pub fn checksum(&self) -> u32 {
+        Self::checksum_players(&self.players)
+    }
+
     fn update_graphics(&mut self, curr_player: usize) {
-        let mut positions = vec![];
-        let mut colors = vec![];
-        let mut indices = vec![];
+        for view in 0..self.local_players {
+            let (cam_pos, cam_theta, cam_phi) = self.camera(view);
+            self.graphics.set_view_camera(view, cam_pos, cam_theta, cam_phi);
+        }
+        let (cam_pos, cam_theta, cam_phi) = self.camera(curr_player);
+        let aim = self.aim(curr_player);
+        let show_aim = aim.hit && self.players[curr_player].grapples.iter().any(|grapple| grapple.is_none());
+
+        let graphics = &mut self.graphics;
+        graphics.clear();
+
+        // the camera goes first since culling depends on it
+        graphics.set_camera(cam_pos, cam_theta, cam_phi);
 
-        let mut index = 0;
         for block in &self.blocks {
-            positions.append(&mut Self::get_block_vertices(&block.origin, &block.dims));
-            indices.append(&mut Self::get_block_indices(&mut index));
-        }
-
-        positions.append(&mut Self::get_block_vertices(&self.win_block.origin, &self.win_block.dims));
-        indices.append(&mut Self::get_block_indices(&mut index));
-
-        let mut floor_colors = vec![
-            0.5,  0.5,  0.5,  1.0,
-            0.5,  0.5,  0.5,  1.0,
-            0.5,  0.5,  0.5,  1.0,
-            0.5,  0.5,  0.5,  1.0,
-            0.5,  0.5,  0.5,  1.0,
-            0.5,  0.5,  0.5,  1.0,
-        ];
-
-        colors.append(&mut floor_colors);
-
-        let color_pattern = vec![
-            1.0,  0.0,  0.0,  1.0,
-            0.0,  1.0,  0.0,  1.0,
-            0.0,  0.0,  1.0,  1.0,
-            1.0,  1.0,  0.0,  1.0,
-            1.0,  0.0,  1.0,  1.0,
-            0.0,  1.0,  1.0,  1.0,
-        ];
-        for block in 0..(self.blocks.len() - 1) {
-            for index in 0..color_pattern.len() {
-                colors.push(color_pattern[(block * 4 + index) % color_pattern.len()]);
-            }
+            graphics.push_block(&block.origin, &block.dims, &self.palette.material(block.material));
+        }
+
+        graphics.push_block(&self.win_block.origin, &self.win_block.dims, &self.palette.win());
+
+        // HIT MARKER
+        if show_aim {
+            let size = 0.01 * aim.distance.max(1.);
+            let dims = Vec3::new(size, size, size);
+            graphics.push_block(&(Vec3::new(aim.x, aim.y, aim.z) - dims / 2.), &dims, &self.palette.grapple());
         }
 
         let mut player_index = 0;
         for player in &self.players {
             // PLAYER VIS
             if player_index != curr_player {
-                positions.append(&mut Self::get_block_vertices(&(player.position - player.dims / 2.), &player.dims));
-                indices.append(&mut Self::get_block_indices(&mut index));
-                for index in 0..color_pattern.len() {
-                    colors.push(color_pattern[(player_index * 4 + index) % color_pattern.len()]);
-                }
+                graphics.push_block(&(player.position - player.dims / 2.), &player.dims, &self.palette.player(player_index));
             }
             player_index += 1;
 
             // GRAPPLE VIS
-            if let Some(grapple) = &player.grapple {
+            for grapple in player.grapples.iter().flatten() {
                 let grapple_width = 0.005;
                 let h_dir = Vec3::new(player.theta().cos(), 0., -player.theta().sin());
-                let start = player.position;
-                let end = grapple.end;
-                
-                positions.append(&mut (start + h_dir * grapple_width).to_vec());
-                positions.append(&mut (start - h_dir * grapple_width).to_vec());
-                positions.append(&mut (end - h_dir * grapple_width).to_vec());
-                positions.append(&mut (end + h_dir * grapple_width).to_vec());
-                
-                let mut new_indices = vec![0, 1, 2, 0, 2, 3];
-                for new_index in &mut new_indices {
-                    *new_index += index;
+
+                // one quad per segment, from the player back through the pivots
+                let mut points = vec![player.position];
+                points.extend(grapple.pivots.iter().rev().map(|pivot| pivot.point));
+                points.push(grapple.end);
+                for segment in points.windows(2) {
+                    let (start, end) = (segment[0], segment[1]);
+                    for vertex in &[
+                        start + h_dir * grapple_width,
+                        start - h_dir * grapple_width,
+                        end - h_dir * grapple_width,
+                        end + h_dir * grapple_width,
+                    ] {
+                        graphics.positions.extend_from_slice(&[vertex.x, vertex.y, vertex.z]);
+                    }
+                    let normal = h_dir.cross(&(end - start)).unit();
+                    for _vertex in 0..4 {
+                        graphics.normals.extend_from_slice(&[normal.x, normal.y, normal.z]);
+                    }
+
+                    graphics.push_indices(&[0, 1, 2, 0, 2, 3], 4);
+
+                    graphics.push_face_color(&self.palette.grapple());
                 }
-                indices.append(&mut new_indices);
-                index += 4;
-    
-                colors.append(&mut vec![1.0, 1.0, 1.0, 1.0]);
-            }
-        }
-        
-        let cam_pos = self.players[curr_player].position + Vec3::new(0., self.players[curr_player].dims.y / 25., 0.);
-
-        self.graphics.update(positions, colors, indices, cam_pos.to_vec(), self.players[curr_player].theta(), -self.players[curr_player].phi());
-    }
-
-    fn get_block_vertices(origin: &Vec3, dims: &Vec3) -> Vec<f32> {
-        let vertices = vec![
-            *origin,
-            *origin + Vec3::new(dims.x, 0., 0.),
-            *origin + Vec3::new(dims.x, dims.y, 0.),
-            *origin + Vec3::new(0., dims.y, 0.),
-
-            *origin,
-            *origin + Vec3::new(dims.x, 0., 0.),
-            *origin + Vec3::new(dims.x, 0., dims.z),
-            *origin + Vec3::new(0., 0., dims.z),
-
-            *origin,
-            *origin + Vec3::new(0., 0., dims.z),
-            *origin + Vec3::new(0., dims.y, dims.z),
-            *origin + Vec3::new(0., dims.y, 0.),
-
-            *origin + *dims,
-            *origin + *dims - Vec3::new(dims.x, 0., 0.),
-            *origin + *dims - Vec3::new(dims.x, dims.y, 0.),
-            *origin + *dims - Vec3::new(0., dims.y, 0.),
-
-            *origin + *dims,
-            *origin + *dims - Vec3::new(dims.x, 0., 0.),
-            *origin + *dims - Vec3::new(dims.x, 0., dims.z),
-            *origin + *dims - Vec3::new(0., 0., dims.z),
-
-            *origin + *dims,
-            *origin + *dims - Vec3::new(0., 0., dims.z),
-            *origin + *dims - Vec3::new(0., dims.y, dims.z),
-            *origin + *dims - Vec3::new(0., dims.y, 0.),
-        ];
-        let mut vertex_vec = Vec::new();
-        for vertex in &vertices {
-            vertex_vec.append(&mut vertex.to_vec());
-        }
-        vertex_vec
-    }
-
-    fn get_block_indices(index: &mut u32) -> Vec<u32> {
-        let mut indices = Vec::new();
-        let new_indices = vec![0, 1, 2, 0, 2, 3];
-        for _face in 0..6 {
-            let mut next_indices = new_indices.clone();
-            for new_index in &mut next_indices {
-                *new_index += *index;
             }
-            indices.append(&mut next_indices);
-            *index += 4;
         }
-        indices
     }
 
     pub fn player_input(&mut self, curr_player: usize, input: Input) {
-        let mut player = &mut self.players[curr_player];
+        self.record_input(curr_player, input);
+        // held movement carries over to go, but nothing leaves the start early
+        if self.countdown > 0 && (input == Input::Jump || input == Input::Cast || input == Input::CastRight) {
+            return
+        }
         match input {
-            Input::GoLeft => player.go(Go::Left),
-            Input::GoForward => player.go(Go::Forward),
-            Input::GoRight => player.go(Go::Right),
-            Input::GoBack => player.go(Go::Back),
-            Input::StopLeft => player.stop(Go::Left),
-            Input::StopForward => player.stop(Go::Forward),
-            Input::StopRight => player.stop(Go::Right),
-            Input::StopBack => player.stop(Go::Back),
-            Input::Jump => player.go(Go::Jump),
-            Input::Cast => player.cast_grapple(),
-            Input::Pull => player.pull_grapple(),
-            Input::Release => player.release_grapple(),
+            Input::GoLeft => self.players[curr_player].go(Go::Left),
+            Input::GoForward => self.players[curr_player].go(Go::Forward),
+            Input::GoRight => self.players[curr_player].go(Go::Right),
+            Input::GoBack => self.players[curr_player].go(Go::Back),
+            Input::StopLeft => self.players[curr_player].stop(Go::Left),
+            Input::StopForward => self.players[curr_player].stop(Go::Forward),
+            Input::StopRight => self.players[curr_player].stop(Go::Right),
+            Input::StopBack => self.players[curr_player].stop(Go::Back),
+            Input::Jump => self.players[curr_player].go(Go::Jump),
+            Input::Cast => self.cast(curr_player, Hand::Left),
+            Input::CastRight => self.cast(curr_player, Hand::Right),
+            Input::Pull => self.players[curr_player].pull_grapple(Hand::Left),
+            Input::Release => self.players[curr_player].stop_pulling(Hand::Left),
+            Input::PullRight => self.players[curr_player].pull_grapple(Hand::Right),
+            Input::ReleaseRight => self.players[curr_player].stop_pulling(Hand::Right),
+            Input::LookLeft => self.players[curr_player].look(0, true),
+            Input::LookRight => self.players[curr_player].look(1, true),
+            Input::LookUp => self.players[curr_player].look(2, true),
+            Input::LookDown => self.players[curr_player].look(3, true),
+            Input::StopLookLeft => self.players[curr_player].look(0, false),
+            Input::StopLookRight => self.players[curr_player].look(1, false),
+            Input::StopLookUp => self.players[curr_player].look(2, false),
+            Input::StopLookDown => self.players[curr_player].look(3, false),
         }
     }
 
-    pub fn cast_grapple(&mut self) {
-        self.players[0].cast_grapple();
+    // Casts where the player aims, aim assist and all, or takes back a grapple already out.
+    fn cast(&mut self, curr_player: usize, hand: Hand) {
+        let cast_dir = self.cast_dir(curr_player);
+        self.players[curr_player].cast_grapple_toward(hand, cast_dir);
+    }
+
+    // The ones below are for player 0, and go through player_input so they're recorded.
+
+    pub fn cast_grapple(&mut self, hand: Hand) {
+        self.player_input(0, match hand { Hand::Left => Input::Cast, Hand::Right => Input::CastRight });
     }
 
-    pub fn pull_grapple(&mut self) {
-        self.players[0].pull_grapple();
+    pub fn pull_grapple(&mut self, hand: Hand) {
+        self.player_input(0, match hand { Hand::Left => Input::Pull, Hand::Right => Input::PullRight });
     }
 
-    pub fn release_grapple(&mut self) {
-        self.players[0].release_grapple();
+    pub fn release_grapple(&mut self, hand: Hand) {
+        self.player_input(0, match hand { Hand::Left => Input::Release, Hand::Right => Input::ReleaseRight });
     }
 
     pub fn go(&mut self, go: Go) {
-        self.players[0].go(go);
+        self.player_input(0, match go {
+            Go::Left => Input::GoLeft,
+            Go::Forward => Input::GoForward,
+            Go::Right => Input::GoRight,
+            Go::Back => Input::GoBack,
+            Go::Jump => Input::Jump,
+        });
     }
 
     pub fn stop(&mut self, go: Go) {
-        self.players[0].stop(go);
+        let input = match go {
+            Go::Left => Input::StopLeft,
+            Go::Forward => Input::StopForward,
+            Go::Right => Input::StopRight,
+            Go::Back => Input::StopBack,
+            Go::Jump => return,
+        };
+        self.player_input(0, input);
     }
 
+    // A bound control pressed or let go. Actions for the page, and players
+    // who aren't in this universe, are ignored.
+    pub fn action(&mut self, curr_player: usize, action: Action, pressed: bool) {
+        if curr_player >= self.players.len() {
+            return
+        }
+        let action = match action {
+            Action::LookUp if self.settings.invert_y => Action::LookDown,
+            Action::LookDown if self.settings.invert_y => Action::LookUp,
+            _ => action,
+        };
+        if let Some(input) = action.input(pressed) {
+            self.player_input(curr_player, input);
+        }
+    }
+
+    // A gamepad stick, straight from the Gamepad API: x right and y down,
+    // each from -1 to 1. The deadzone is taken out here.
+    pub fn move_stick(&mut self, curr_player: usize, x: f32, y: f32) {
+        if curr_player < self.players.len() {
+            self.stick_input(curr_player, Stick::Move, deadzone(x, -y));
+        }
+    }
+
+    pub fn look_stick(&mut self, curr_player: usize, x: f32, y: f32) {
+        let y = if self.settings.invert_y { -y } else { y };
+        if curr_player < self.players.len() {
+            self.stick_input(curr_player, Stick::Look, deadzone(x, y));
+        }
+    }
+
+    // Scaled by the look settings here, before it's recorded, so replays
+    // turn the same whatever the settings are when they're played.
     pub fn mouse_look(&mut self, curr_player: usize, movement_x: f32, movement_y: f32) {
-        self.players[curr_player].mouse_look(movement_x, movement_y);
+        let settings = &self.settings;
+        let invert = if settings.invert_y { -1. } else { 1. };
+        let movement_x = movement_x * settings.sensitivity * settings.sensitivity_x;
+        let movement_y = movement_y * settings.sensitivity * settings.sensitivity_y * invert;
+        self.look_input(curr_player, movement_x, movement_y);
     }
+}
+
+graphics_accessors!(Universe);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::master::{Master, COUNTDOWN_TICKS};
+
+    #[test]
+    fn countdown_goes_in_whole_ticks_and_survives_snapshots() {
+        let master = Master::new();
+        let mut universe = Universe::new(master.level(0).unwrap(), 1);
+        universe.set_countdown(10);
+        // frames a bit short of a tick still count down one tick per tick's worth
+        for _frame in 0..4 {
+            universe.step(TICK_TIME * 0.9);
+        }
+        assert_eq!(universe.countdown, 7);
+        let saved = Snapshot::deserialize(&universe.snapshot().serialize()).unwrap();
+        for _tick in 0..10 {
+            universe.step(TICK_TIME);
+        }
+        assert!(!universe.counting_down());
 
-    pub fn graphics(&self) -> Graphics {
-        self.graphics.clone()
+        universe.restore(&saved);
+        assert!(universe.counting_down());
+        for _tick in 0..6 {
+            universe.step(TICK_TIME);
+        }
+        assert!(universe.counting_down());
+        universe.step(TICK_TIME);
+        assert!(!universe.counting_down());
+        assert_eq!(universe.run_time(), 0.);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn restoring_takes_back_the_win_and_its_events() {
+        let master = Master::new();
+        let mut universe = Universe::new(master.level(0).unwrap(), 1);
+        let saved = universe.snapshot();
+        universe.won = true;
+        universe.events.push(Event::new(EventKind::LevelWon));
+        universe.restore(&saved);
+        assert!(!universe.won);
+        assert_eq!(universe.take_events(), 0);
+    }
+
+    #[test]
+    fn look_sensitivity_scales_held_look() {
+        let master = Master::new();
+        let mut plain = Universe::new(master.level(0).unwrap(), 1);
+        let mut scaled = Universe::new(master.level(0).unwrap(), 1);
+        let mut settings = Settings::new();
+        settings.sensitivity = 2.;
+        settings.sensitivity_x = 1.5;
+        scaled.set_settings(&settings);
+        for universe in [&mut plain, &mut scaled] {
+            universe.player_input(0, Input::LookRight);
+            universe.stick_input(0, Stick::Look, (0., 0.5));
+            for _tick in 0..10 {
+                universe.step(TICK_TIME);
+            }
+        }
+        let (plain, scaled) = (&plain.players[0], &scaled.players[0]);
+        assert!((scaled.theta() - plain.theta() * 3.).abs() < 1e-5);
+        assert!((scaled.phi() - plain.phi() * 2.).abs() < 1e-5);
+    }
+
+    #[test]
+    fn pausing_stops_pulling_but_keeps_the_hook() {
+        let master = Master::new();
+        let mut universe = Universe::new(master.level(0).unwrap(), 1);
+        let position = universe.players[0].position;
+        let mut grapple = Grapple::new(position, position + Vec3::new(0., 5., 0.));
+        grapple.hooked = true;
+        universe.players[0].grapples[Hand::Left as usize] = Some(grapple);
+        universe.player_input(0, Input::Pull);
+        universe.pause();
+        let player = &universe.players[0];
+        assert!(!player.pulling(Hand::Left));
+        assert!(player.grapple(Hand::Left).is_some_and(|grapple| grapple.hooked));
+    }
+
+    #[test]
+    fn aim_assist_at_any_point_keeps_a_run_off_the_records() {
+        let master = Master::new();
+        let mut universe = Universe::new(master.level(0).unwrap(), 1);
+        assert!(universe.counts_for_records());
+        universe.set_aim_assist(true);
+        universe.set_aim_assist(false);
+        assert!(!universe.counts_for_records());
+        universe.restart();
+        assert!(universe.counts_for_records());
+    }
+
+    #[test]
+    fn look_scale_survives_restarts_and_their_replays() {
+        let master = Master::new();
+        let mut universe = Universe::new(master.level(0).unwrap(), 1);
+        universe.set_recording(true);
+        let mut settings = Settings::new();
+        settings.sensitivity = 2.;
+        universe.set_settings(&settings);
+        universe.step(TICK_TIME);
+        universe.restart();
+        assert_eq!(universe.players[0].look_scale(), (2., 2.));
+        universe.set_countdown(0);
+        universe.player_input(0, Input::LookRight);
+        for _tick in 0..10 {
+            universe.step(TICK_TIME);
+        }
+        let mut replayed = Universe::new(master.level(0).unwrap(), 1);
+        universe.replay().unwrap().play(&mut replayed);
+        assert_eq!(replayed.players[0].theta(), universe.players[0].theta());
+    }
+
+    #[test]
+    fn held_look_turns_the_camera_before_go() {
+        let master = Master::new();
+        let mut universe = Universe::new(master.level(0).unwrap(), 1);
+        universe.set_countdown(COUNTDOWN_TICKS);
+        let theta = universe.players[0].theta();
+        universe.player_input(0, Input::LookRight);
+        universe.step(TICK_TIME);
+        assert!(universe.counting_down());
+        assert!(universe.players[0].theta() != theta);
+    }
+}
@@ -40,6 +40,14 @@ impl Vec3 {
         self.x * other.x + self.y * other.y + self.z * other.z
     }
 
+    pub fn cross(&self, other: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.y * other.z - self.z * other.y,
+            self.z * other.x - self.x * other.z,
+            self.x * other.y - self.y * other.x,
+        )
+    }
+
     pub fn project_onto(&self, other: &Vec3) -> Vec3 {
         if other.length() == 0. {
             return other.clone()
@@ -132,6 +140,59 @@ pub trait AABB {
     
     fn max(&self) -> Vec3;
 
+    fn contains(&self, point: &Vec3) -> bool {
+        let min = self.min();
+        let max = self.max();
+        point.x >= min.x && point.x <= max.x &&
+        point.y >= min.y && point.y <= max.y &&
+        point.z >= min.z && point.z <= max.z
+    }
+
+    // From the point to the nearest part of the box, 0 if it's inside.
+    fn distance_to(&self, point: &Vec3) -> f32 {
+        let min = self.min();
+        let max = self.max();
+        let outside = Vec3::new(
+            (min.x - point.x).max(point.x - max.x).max(0.),
+            (min.y - point.y).max(point.y - max.y).max(0.),
+            (min.z - point.z).max(point.z - max.z).max(0.),
+        );
+        outside.length()
+    }
+
+    // Distance along a unit ray to where it enters the box, 0 if it starts inside.
+    fn ray_hit(&self, origin: &Vec3, dir: &Vec3) -> Option<f32> {
+        let min = self.min();
+        let max = self.max();
+        let mut t_near = 0f32;
+        let mut t_far = f32::INFINITY;
+        for (o, d, lo, hi) in [
+            (origin.x, dir.x, min.x, max.x),
+            (origin.y, dir.y, min.y, max.y),
+            (origin.z, dir.z, min.z, max.z),
+        ].iter() {
+            if *d == 0. {
+                if o < lo || o > hi {
+                    return None
+                }
+                continue
+            }
+            let t_a = (lo - o) / d;
+            let t_b = (hi - o) / d;
+            t_near = t_near.max(t_a.min(t_b));
+            t_far = t_far.min(t_a.max(t_b));
+        }
+        if t_near <= t_far { Some(t_near) } else { None }
+    }
+
+    fn overlaps(&self, other: &impl AABB) -> bool {
+        let (a_min, a_max) = (self.min(), self.max());
+        let (b_min, b_max) = (other.min(), other.max());
+        a_min.x <= b_max.x && a_max.x >= b_min.x &&
+        a_min.y <= b_max.y && a_max.y >= b_min.y &&
+        a_min.z <= b_max.z && a_max.z >= b_min.z
+    }
+
     fn collision(&self, other: &impl AABB, vel: &Vec3) -> Vec3 {
 
         let a_min = self.min();
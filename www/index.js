@@ -1,7 +1,7 @@
 /*import('runner-game')
   .catch(console.error);
 //*/
-import { Master, Universe, Mode } from "runner-game";
+import { Master, Universe, Mode, EventKind, GrappleStatus, Fly, Difficulty, Medal, Action, Control, Device } from "runner-game";
 import { memory } from "runner-game/runner_game_bg";
 const { mat4, mat3, vec3 } = glMatrix;
 
@@ -10,12 +10,23 @@ const GRID_COLOR = "#CCCCCC";
 const DEAD_COLOR = "#FFFFFF";
 const ALIVE_COLOR = "#000000";
 
-let startTime = Date.now();
-
 const master = Master.new();
+
+// progress and settings live in localStorage between sessions
+const SAVE_KEY = "runner-game-save";
+const savedData = localStorage.getItem(SAVE_KEY);
+if (savedData !== null && !master.load_save_data(savedData)) {
+  console.warn("Couldn't read save data, starting fresh.");
+}
+const persist = () => localStorage.setItem(SAVE_KEY, master.save_data());
+
 let universe = null;//master.start(0);
+let editor = null; // kept while playtesting, so "t" can go back to it
+let currentLevel = null; // what finishing counts toward; null when playtesting
 //universe.update(0, 0);
 let myPlayer = 0;
+let localPlayers = master.local_players();
+let aimAssist = master.aim_assist();
 
 let cameraPosition = {
   x: 0,
@@ -26,11 +37,25 @@ let cameraAngle = {
   theta: 0,
   phi: 0,
 }
+let light = {
+  sunDirection: [0, -1, 0],
+  ambient: 1,
+}
+let view = {
+  fieldOfView: 45 * Math.PI / 180,
+  zNear: 0.025,
+  zFar: 100.0,
+  fogStart: 100.0,
+  fogColor: [0.012, 0.647, 0.988, 1.0],
+}
 
-let graphics = null;//universe.graphics();
-let positions = [];//graphics.positions();
-let faceColors = [];//graphics.colors();
-let indices = [];//graphics.indices();
+// Views into the universe's geometry buffers in wasm memory. They are
+// recreated each frame since the buffers (or memory itself) may have grown.
+let positions = new Float32Array(0);
+let colors = new Float32Array(0);
+let normals = new Float32Array(0);
+let indices = new Uint16Array(0);
+let batches = new Uint32Array(0);
 
 function initShaderProgram(gl, vsSource, fsSource) {
   const vertexShader = loadShader(gl, gl.VERTEX_SHADER, vsSource);
@@ -66,101 +91,38 @@ function loadShader(gl, type, source) {
 }
 
 function initBuffers(gl) {
-  const positionBuffer = gl.createBuffer();
-
-  gl.bindBuffer(gl.ARRAY_BUFFER, positionBuffer);
-
-  
-
-  gl.bufferData(gl.ARRAY_BUFFER,
-                new Float32Array(positions),
-                gl.STATIC_DRAW);
-
-
-  if (faceColors.length % 4 !== 0) {
-    throw 'faceColors not divisible by 4!';
-  }
-
-  let colors = [];
-  for (let j = 0; j < faceColors.length / 4; ++j) {
-    const c = [];
-    for (let i = 0; i < 4; ++i) {
-      c.push(faceColors[4* j + i]);
-    }
-    colors = colors.concat(c, c, c, c);
-  }
-
-  const colorBuffer = gl.createBuffer();
-  gl.bindBuffer(gl.ARRAY_BUFFER, colorBuffer);
-  gl.bufferData(gl.ARRAY_BUFFER, new Float32Array(colors), gl.STATIC_DRAW);
-
-  //---
-
-  const indexBuffer = gl.createBuffer();
-  gl.bindBuffer(gl.ELEMENT_ARRAY_BUFFER, indexBuffer);
-
-  
-
-  gl.bufferData(gl.ELEMENT_ARRAY_BUFFER, new Uint16Array(indices), gl.STATIC_DRAW);
-
   return {
-    position: positionBuffer,
-    color: colorBuffer,
-    indices: indexBuffer,
+    position: gl.createBuffer(),
+    color: gl.createBuffer(),
+    normal: gl.createBuffer(),
+    indices: gl.createBuffer(),
   };
 }
 
-function drawScene(gl, programInfo, buffers) {
-  /*
+function uploadBuffers(gl, buffers) {
   gl.bindBuffer(gl.ARRAY_BUFFER, buffers.position);
-  
-  gl.bufferData(gl.ARRAY_BUFFER,
-                new Float32Array(positions),
-                gl.STATIC_DRAW);
-  */
-  buffers = initBuffers(gl);
-  
-  gl.clearColor(0.012, 0.647, 0.988, 1.0);
-  gl.clearDepth(1.0);
-  gl.enable(gl.DEPTH_TEST);
-  gl.depthFunc(gl.LEQUAL);
+  gl.bufferData(gl.ARRAY_BUFFER, positions, gl.DYNAMIC_DRAW);
 
-  gl.clear(gl.COLOR_BUFFER_BIT | gl.DEPTH_BUFFER_BIT);
+  gl.bindBuffer(gl.ARRAY_BUFFER, buffers.color);
+  gl.bufferData(gl.ARRAY_BUFFER, colors, gl.DYNAMIC_DRAW);
 
-  const fieldOfView = 45 * Math.PI / 180;
-  const aspect = gl.canvas.clientWidth / gl.canvas.clientHeight;
-  const zNear = 0.025;
-  const zFar = 100.0;
+  gl.bindBuffer(gl.ARRAY_BUFFER, buffers.normal);
+  gl.bufferData(gl.ARRAY_BUFFER, normals, gl.DYNAMIC_DRAW);
 
-  const projectionMatrix = mat4.create();
-  mat4.perspective(projectionMatrix,
-                  fieldOfView,
-                  aspect,
-                  zNear,
-                  zFar);
+  gl.bindBuffer(gl.ELEMENT_ARRAY_BUFFER, buffers.indices);
+  gl.bufferData(gl.ELEMENT_ARRAY_BUFFER, indices, gl.DYNAMIC_DRAW);
+}
 
-  const modelViewMatrix = mat4.create();
-  mat4.rotate(modelViewMatrix,
-    modelViewMatrix,
-    cameraAngle.phi,
-    [1, 0, 0]);
-  mat4.rotate(modelViewMatrix,
-    modelViewMatrix,
-    cameraAngle.theta,
-    [0, 1, 0]);
-  mat4.scale(modelViewMatrix,
-    modelViewMatrix,
-    [1, 1, -1]);
-  mat4.translate(modelViewMatrix,
-    modelViewMatrix,
-    [-cameraPosition.x, -cameraPosition.y, -cameraPosition.z]);
+// Points the vertex attributes at a batch's first vertex, so its 16-bit
+// indices can stay relative to the batch.
+function bindAttributes(gl, programInfo, buffers, vertexStart) {
   {
     const numComponents = 3;  // pull out 2 values per iteration - 2d..?
     const type = gl.FLOAT;    // the data in the buffer is 32bit floats
     const normalize = false;  // don't normalize
     const stride = 0;         // how many bytes to get from one set of values to the next
                               // 0 = use type and numComponents above
-    const offset = 0;         // how many bytes inside the buffer to start from
+    const offset = vertexStart * numComponents * 4; // how many bytes inside the buffer to start from
     gl.bindBuffer(gl.ARRAY_BUFFER, buffers.position);
     gl.vertexAttribPointer(
         programInfo.attribLocations.vertexPosition,
@@ -177,7 +139,7 @@ function drawScene(gl, programInfo, buffers) {
     const type = gl.FLOAT;
     const normalize = false;
     const stride = 0;
-    const offset = 0;
+    const offset = vertexStart * numComponents * 4;
     gl.bindBuffer(gl.ARRAY_BUFFER, buffers.color);
     gl.vertexAttribPointer(
         programInfo.attribLocations.vertexColor,
@@ -189,7 +151,71 @@ function drawScene(gl, programInfo, buffers) {
     gl.enableVertexAttribArray(
         programInfo.attribLocations.vertexColor);
   }
+  {
+    const numComponents = 3;
+    const type = gl.FLOAT;
+    const normalize = false;
+    const stride = 0;
+    const offset = vertexStart * numComponents * 4;
+    gl.bindBuffer(gl.ARRAY_BUFFER, buffers.normal);
+    gl.vertexAttribPointer(
+        programInfo.attribLocations.vertexNormal,
+        numComponents,
+        type,
+        normalize,
+        stride,
+        offset);
+    gl.enableVertexAttribArray(
+        programInfo.attribLocations.vertexNormal);
+  }
+}
 
+// viewport is (x, y, width, height) as fractions of the canvas
+function drawScene(gl, programInfo, buffers, viewport) {
+  uploadBuffers(gl, buffers);
+
+  const x = viewport[0] * gl.canvas.width;
+  const y = viewport[1] * gl.canvas.height;
+  const width = viewport[2] * gl.canvas.width;
+  const height = viewport[3] * gl.canvas.height;
+  gl.viewport(x, y, width, height);
+  gl.scissor(x, y, width, height);
+  gl.enable(gl.SCISSOR_TEST);
+
+  gl.clearColor(view.fogColor[0], view.fogColor[1], view.fogColor[2], view.fogColor[3]);
+  gl.clearDepth(1.0);
+  gl.enable(gl.DEPTH_TEST);
+  gl.depthFunc(gl.LEQUAL);
+
+  gl.clear(gl.COLOR_BUFFER_BIT | gl.DEPTH_BUFFER_BIT);
+
+  const fieldOfView = view.fieldOfView;
+  const aspect = gl.canvas.clientWidth * viewport[2] / (gl.canvas.clientHeight * viewport[3]);
+  const zNear = view.zNear;
+  const zFar = view.zFar;
+
+  const projectionMatrix = mat4.create();
+  mat4.perspective(projectionMatrix,
+                  fieldOfView,
+                  aspect,
+                  zNear,
+                  zFar);
+
+  const modelViewMatrix = mat4.create();
+  mat4.rotate(modelViewMatrix,
+    modelViewMatrix,
+    cameraAngle.phi,
+    [1, 0, 0]);
+  mat4.rotate(modelViewMatrix,
+    modelViewMatrix,
+    cameraAngle.theta,
+    [0, 1, 0]);
+  mat4.scale(modelViewMatrix,
+    modelViewMatrix,
+    [1, 1, -1]);
+  mat4.translate(modelViewMatrix,
+    modelViewMatrix,
+    [-cameraPosition.x, -cameraPosition.y, -cameraPosition.z]);
   gl.bindBuffer(gl.ELEMENT_ARRAY_BUFFER, buffers.indices);
 
   // Tell WebGL to use our program when drawing
@@ -206,12 +232,29 @@ function drawScene(gl, programInfo, buffers) {
       programInfo.uniformLocations.modelViewMatrix,
       false,
       modelViewMatrix);
-
-  {
-
-    const vertexCount = positions.length / 2;
-    const type = gl.UNSIGNED_SHORT;
-    const offset = 0;
+  gl.uniform3fv(
+      programInfo.uniformLocations.sunDirection,
+      light.sunDirection);
+  gl.uniform1f(
+      programInfo.uniformLocations.ambient,
+      light.ambient);
+  gl.uniform1f(
+      programInfo.uniformLocations.fogStart,
+      view.fogStart);
+  gl.uniform1f(
+      programInfo.uniformLocations.fogEnd,
+      view.zFar);
+  gl.uniform4fv(
+      programInfo.uniformLocations.fogColor,
+      view.fogColor);
+
+  // batches are (index start, index count, vertex start) triples
+  for (let i = 0; i < batches.length; i += 3) {
+    bindAttributes(gl, programInfo, buffers, batches[i + 2]);
+
+    const vertexCount = batches[i + 1];
+    const type = indices.BYTES_PER_ELEMENT === 4 ? gl.UNSIGNED_INT : gl.UNSIGNED_SHORT;
+    const offset = batches[i] * indices.BYTES_PER_ELEMENT;
     gl.drawElements(gl.TRIANGLES, vertexCount, type, offset);
   }
 }
@@ -237,25 +280,42 @@ function main() {
   const vsSource = `
     attribute vec4 aVertexPosition;
     attribute vec4 aVertexColor;
+    attribute vec3 aVertexNormal;
 
     uniform mat4 uModelViewMatrix;
     uniform mat4 uProjectionMatrix;
+    uniform vec3 uSunDirection;
+    uniform float uAmbient;
 
     varying lowp vec4 vColor;
+    varying highp float vDistance;
 
     void main() {
-      gl_Position = uProjectionMatrix * uModelViewMatrix * aVertexPosition;
-      vColor = aVertexColor;
+      vec4 viewPosition = uModelViewMatrix * aVertexPosition;
+      gl_Position = uProjectionMatrix * viewPosition;
+      vDistance = length(viewPosition.xyz);
+      // normals are in world space, same as the sun direction
+      float diffuse = max(dot(aVertexNormal, -uSunDirection), 0.0);
+      float brightness = uAmbient + (1.0 - uAmbient) * diffuse;
+      vColor = vec4(aVertexColor.rgb * brightness, aVertexColor.a);
     }
 
   `;
 
   // Fragment shader
   const fsSource = `
+    precision mediump float;
+
+    uniform float uFogStart;
+    uniform float uFogEnd;
+    uniform vec4 uFogColor;
+
     varying lowp vec4 vColor;
+    varying highp float vDistance;
 
     void main() {
-      gl_FragColor = vColor;
+      float fog = clamp((vDistance - uFogStart) / (uFogEnd - uFogStart), 0.0, 1.0);
+      gl_FragColor = mix(vColor, uFogColor, fog);
     }
   `;
 
@@ -266,57 +326,277 @@ function main() {
     attribLocations: {
       vertexPosition: gl.getAttribLocation(shaderProgram, 'aVertexPosition'),
       vertexColor: gl.getAttribLocation(shaderProgram, 'aVertexColor'),
+      vertexNormal: gl.getAttribLocation(shaderProgram, 'aVertexNormal'),
     },
     uniformLocations: {
       projectionMatrix: gl.getUniformLocation(shaderProgram, 'uProjectionMatrix'),
       modelViewMatrix: gl.getUniformLocation(shaderProgram, 'uModelViewMatrix'),
+      sunDirection: gl.getUniformLocation(shaderProgram, 'uSunDirection'),
+      ambient: gl.getUniformLocation(shaderProgram, 'uAmbient'),
+      fogStart: gl.getUniformLocation(shaderProgram, 'uFogStart'),
+      fogEnd: gl.getUniformLocation(shaderProgram, 'uFogEnd'),
+      fogColor: gl.getUniformLocation(shaderProgram, 'uFogColor'),
     }
   };
 
   const buffers = initBuffers(gl);
+  const u32Indices = gl.getExtension('OES_element_index_uint') !== null;
 
-  const INPUT = {
-    "goleft": 0,
-    "goforward": 1,
-    "goright": 2,
-    "goback": 3,
-    "stopleft": 4,
-    "stopforward": 5,
-    "stopright": 6,
-    "stopback": 7,
-    "jump": 8,
-    "cast": 9,
-    "pull": 10,
-    "release": 11,
-  }
 
-  
-  const levels = document.getElementsByClassName("level");
   const startLevel = (level) => {
-    universe = master.start(level);
+    // locked levels don't start
+    const started = master.start(level, localPlayers);
+    if (!started) {
+      return;
+    }
+    universe = started;
+    currentLevel = level;
+    universe.set_u32_indices(u32Indices);
+    master.set_mode(Mode.Countdown);
+    document.getElementById("menu").style.visibility = 'hidden';
+  };
+
+  // input goes to the universe before go too, where it decides what counts
+  const inRun = () => master.mode() === Mode.Play || master.mode() === Mode.Countdown;
+
+  const MEDAL_NAMES = ["", "Bronze", "Silver", "Gold"]; // indexed by Medal
+
+  // one heading per pack, then its levels, ahead of the other options
+  const renderCatalog = () => {
+    const options = document.querySelector("#menu .options");
+    const daily = document.getElementById("daily");
+    for (const old of options.querySelectorAll(".catalog")) {
+      old.remove();
+    }
+    for (let p = 0; p < master.num_packs(); ++p) {
+      const pack = master.pack_info(p);
+      const heading = document.createElement("div");
+      heading.className = "catalog pack";
+      heading.innerHTML = pack.name();
+      heading.title = pack.description();
+      options.insertBefore(heading, daily);
+      for (let l = 0; l < pack.levels; ++l) {
+        const info = master.level_info(p, l);
+        const option = document.createElement("div");
+        option.className = "catalog option" + (info.unlocked ? "" : " locked");
+        option.title = info.description();
+        let label = info.name();
+        if (!info.unlocked) {
+          label += "<br>Locked";
+        } else if (info.best_time > 0) {
+          label += "<br>" + (info.best_time / 1000).toFixed(2) + "s " + MEDAL_NAMES[info.medal];
+        }
+        option.innerHTML = label;
+        const level = info.level;
+        if (info.unlocked) {
+          option.onclick = () => startLevel(level);
+        }
+        options.insertBefore(option, daily);
+        info.free();
+      }
+      pack.free();
+    }
+  };
+  renderCatalog();
+
+  // the same date gives everyone the same generated level
+  const dailySeed = () => {
+    const now = new Date();
+    return now.getUTCFullYear() * 10000 + (now.getUTCMonth() + 1) * 100 + now.getUTCDate();
+  };
+  const dailyLevels = {};
+  document.getElementById("daily").onclick = () => {
+    const seed = dailySeed();
+    if (!(seed in dailyLevels)) {
+      dailyLevels[seed] = master.generate(seed, Difficulty.normal());
+    }
+    // nothing generated for the seed could be shown to be beatable
+    if (dailyLevels[seed] === undefined) {
+      console.log("no daily level for " + seed);
+      return;
+    }
+    startLevel(dailyLevels[seed]);
+  };
+
+  // editing a level past the last one starts from a blank level
+  // levels added later move past the end, so "new" is kept apart from any index
+  let editTarget = null; // null for a new level
+  const editTargetToggle = document.getElementById("editTarget");
+  editTargetToggle.onclick = () => {
+    const next = editTarget === null ? 0 : editTarget + 1;
+    editTarget = next < master.num_levels() ? next : null;
+    editTargetToggle.innerHTML = "Edit: " + (editTarget === null ? "New" : "Level " + (editTarget + 1));
+  };
+  document.getElementById("edit").onclick = () => {
+    editor = master.edit(editTarget === null ? master.num_levels() : editTarget);
+    editor.set_u32_indices(u32Indices);
+    universe = null;
+    master.set_mode(Mode.Edit);
+    document.getElementById("menu").style.visibility = 'hidden';
+  };
+
+  const playtest = () => {
+    universe = master.playtest(editor);
+    currentLevel = null;
+    universe.set_u32_indices(u32Indices);
+    universe.set_aim_assist(aimAssist);
     master.set_mode(Mode.Play);
+  };
+
+  const backToEditor = () => {
+    universe = null;
+    showHudViews(0);
+    document.getElementById("endMenu").style.visibility = 'hidden';
+    master.set_mode(Mode.Edit);
+  };
+
+  const practiceToggle = document.getElementById("practice");
+  const showPractice = () => practiceToggle.innerHTML = "Practice: " + (master.practice() ? "On" : "Off");
+  practiceToggle.onclick = () => {
+    master.set_practice(!master.practice());
+    showPractice();
+    persist();
+  };
+  showPractice();
+
+  const playerCount = document.getElementById("players");
+  const showPlayers = () => playerCount.innerHTML = "Players: " + localPlayers;
+  playerCount.onclick = () => {
+    localPlayers = localPlayers % 4 + 1;
+    master.set_local_players(localPlayers);
+    showPlayers();
+    persist();
+  };
+  showPlayers();
+
+  // One row per action, with the key or mouse button for each keyboard
+  // player and the button all gamepads share. Clicking one waits for the
+  // next press to bind in its place.
+  const ACTION_NAMES = [
+    [Action.MoveLeft, "Left"],
+    [Action.MoveForward, "Forward"],
+    [Action.MoveRight, "Right"],
+    [Action.MoveBack, "Back"],
+    [Action.Jump, "Jump"],
+    [Action.Cast, "Cast"],
+    [Action.Pull, "Pull"],
+    [Action.CastRight, "Cast right"],
+    [Action.PullRight, "Pull right"],
+    [Action.LookLeft, "Look left"],
+    [Action.LookRight, "Look right"],
+    [Action.LookUp, "Look up"],
+    [Action.LookDown, "Look down"],
+    [Action.Pause, "Pause"],
+    [Action.Restart, "Restart"],
+    [Action.ToggleAimAssist, "Aim assist"],
+  ];
+  const BINDING_COLUMNS = [[0, Device.Key], [1, Device.Key], [0, Device.Gamepad]];
+  const controlsMenu = document.getElementById("controlsMenu");
+  const controlsStatus = document.getElementById("controlsStatus");
+  controlsMenu.style.visibility = 'hidden';
+  let rebinding = null; // the player, action and device waiting for a press
+
+  const renderBindings = () => {
+    const table = document.getElementById("bindings");
+    table.innerHTML = "<tr><td></td><td>Player 1</td><td>Player 2</td><td>Gamepad</td></tr>";
+    for (const [action, name] of ACTION_NAMES) {
+      const row = table.insertRow();
+      row.insertCell().innerHTML = name;
+      for (const [player, device] of BINDING_COLUMNS) {
+        const cell = row.insertCell();
+        cell.className = "rebind";
+        cell.innerHTML = master.binding_name(player, action, device) || "-";
+        cell.onclick = () => {
+          rebinding = { player, action, device };
+          if (device === Device.Gamepad) {
+            controlsStatus.innerHTML = "Press a gamepad button for " + name + ", or Escape";
+            pollRebind();
+          } else {
+            controlsStatus.innerHTML = "Press a key or mouse button for " + name + ", or Escape";
+          }
+        };
+      }
+    }
+  };
+
+  // Binds the press being waited for. A control already doing something
+  // else only moves over if the player says so.
+  const rebind = (control) => {
+    const { player, action, device } = rebinding;
+    rebinding = null;
+    controlsStatus.innerHTML = "";
+    if ((control.device() === Device.Gamepad) === (device === Device.Gamepad)) {
+      const conflict = master.bind(player, action, control);
+      if (conflict.found) {
+        const name = ACTION_NAMES.find(([bound]) => bound === conflict.action)[1];
+        const owner = control.device() === Device.Gamepad ? "gamepads" : "player " + (conflict.player + 1);
+        if (confirm(control.name() + " is already " + name + " for " + owner + ". Use it here instead?")) {
+          master.unbind(control);
+          master.bind(player, action, control).free();
+        }
+      }
+      conflict.free();
+      persist();
+      renderBindings();
+    }
+    control.free();
+  };
+
+  const pollRebind = () => {
+    if (!rebinding || rebinding.device !== Device.Gamepad) {
+      return;
+    }
+    const gamepads = navigator.getGamepads ? navigator.getGamepads() : [];
+    for (const pad of gamepads) {
+      const button = pad ? pad.buttons.findIndex(button => button.pressed) : -1;
+      if (button !== -1) {
+        rebind(Control.gamepad(button));
+        return;
+      }
+    }
+    requestAnimationFrame(pollRebind);
+  };
+
+  document.getElementById("controls").onclick = () => {
+    renderBindings();
     document.getElementById("menu").style.visibility = 'hidden';
-    startTime = Date.now();
+    controlsMenu.style.visibility = 'visible';
+  };
+  document.getElementById("resetBindings").onclick = () => {
+    master.reset_bindings();
+    persist();
+    renderBindings();
+  };
+  document.getElementById("controlsBack").onclick = () => {
+    rebinding = null;
+    controlsStatus.innerHTML = "";
+    controlsMenu.style.visibility = 'hidden';
+    document.getElementById("menu").style.visibility = 'visible';
   };
-  for (let i = 0; i < levels.length; ++i) {
-    levels[i].onclick = () => startLevel(i);
-  }
 
   document.getElementById("endMenu").style.visibility = 'hidden';
   document.getElementById("restart").onclick = () => restart();
   document.getElementById("goMenu").onclick = () => goMenu();
 
   document.addEventListener("mousedown", function (event) {
-    if (master.mode() == Mode.Play) {
+    if (rebinding && rebinding.device !== Device.Gamepad) {
+      rebind(Control.mouse(event.button));
+      return;
+    }
+    if (master.mode() == Mode.Edit) {
       if (document.pointerLockElement === document.body) {
         if (event.button === 0) {
-          //universe.cast_grapple();
-          universe.player_input(myPlayer, INPUT["cast"]);
+          editor.pick();
         } else if (event.button === 2) {
-          universe.player_input(myPlayer, INPUT["pull"]);
-          //universe.pull_grapple();
+          editor.create_block();
         }
-        
+      } else {
+        document.body.requestPointerLock();
+      }
+    }
+    if (inRun()) {
+      if (document.pointerLockElement === document.body) {
+        press(lookup(Control.mouse(event.button)), null, true, false);
       } else {
         document.body.requestPointerLock();
       }
@@ -324,18 +604,18 @@ function main() {
   });
 
   document.addEventListener("mouseup", function (event) {
-    if (master.mode() == Mode.Play) {
+    if (inRun()) {
       if (document.pointerLockElement === document.body) {
-        if (event.button === 2) {
-          universe.player_input(myPlayer, INPUT["release"]);
-          //universe.release_grapple();
-        }
+        press(lookup(Control.mouse(event.button)), null, false, false);
       }
     }
   });
 
   document.body.addEventListener("mousemove", function (event) {
-    if (master.mode() == Mode.Play) {
+    if (master.mode() == Mode.Edit && document.pointerLockElement === document.body) {
+      editor.mouse_look(event.movementX, event.movementY);
+    }
+    if (inRun()) {
       if (document.pointerLockElement === document.body) {
         //console.log("Moved by " + event.movementX + ", " + event.movementY);
         universe.mouse_look(myPlayer, event.movementX, event.movementY);
@@ -343,79 +623,318 @@ function main() {
     }
   });
 
-  const MOVE = {
-    "a": 0,
-    "w": 1,
-    "d": 2,
-    "s": 3,
-    " ": 4,
+  // Local players 0 and 1 share the keyboard, going by the bindings in the
+  // save data, and any further players a gamepad each.
+  const GAMEPAD_FIRST_PLAYER = 2;
+  // held keys repeat; these are fine to press again, the rest are one shot
+  const REPEATABLE = new Set([Action.MoveLeft, Action.MoveForward, Action.MoveRight, Action.MoveBack, Action.Jump]);
+  const gamepadState = {};
+
+  // what a control is bound to, freeing what came from wasm
+  const lookup = (control) => {
+    const bound = master.lookup(control);
+    const result = { found: bound.found, player: bound.player, action: bound.action };
+    bound.free();
+    control.free();
+    return result;
+  };
+
+  // Passes a bound control on to the universe, or handles it here if it's
+  // for the page. Gamepads pass the player they drive.
+  const press = (bound, padPlayer, pressed, repeat) => {
+    const player = padPlayer === null ? bound.player : padPlayer;
+    if (!bound.found || player >= localPlayers) {
+      return;
+    }
+    const action = bound.action;
+    if (!pressed) {
+      universe.action(player, action, false);
+    } else if (repeat && !REPEATABLE.has(action)) {
+      // already handled when first pressed
+    } else if (action === Action.Pause) {
+      // the menu is a click away from the pause menu
+      pause();
+    } else if (action === Action.Restart) {
+      restart();
+    } else if (action === Action.ToggleAimAssist) {
+      aimAssist = !aimAssist;
+      universe.set_aim_assist(aimAssist);
+      master.set_aim_assist(aimAssist);
+      persist();
+    } else {
+      universe.action(player, action, true);
+    }
+  };
+
+  const pollGamepads = () => {
+    const gamepads = navigator.getGamepads ? navigator.getGamepads() : [];
+    for (let i = 0; i < gamepads.length; ++i) {
+      const pad = gamepads[i];
+      const player = GAMEPAD_FIRST_PLAYER + i;
+      if (!pad || player >= localPlayers) {
+        continue;
+      }
+      const prev = gamepadState[i] || { buttons: [] };
+      // sticks go as they are; the universe takes out the deadzone and
+      // only records them when they move
+      universe.move_stick(player, pad.axes[0], pad.axes[1]);
+      universe.look_stick(player, pad.axes[2], pad.axes[3]);
+      // the buttons go by the bindings, which all gamepads share
+      const buttons = pad.buttons.map(button => button.pressed);
+      for (let b = 0; b < buttons.length; ++b) {
+        if (buttons[b] !== (prev.buttons[b] || false)) {
+          press(lookup(Control.gamepad(b)), player, buttons[b], false);
+        }
+      }
+      gamepadState[i] = { buttons };
+    }
   };
 
   const goMenu = () => {
     document.exitPointerLock();
     master.set_mode(Mode.Menu);
     universe = null;
+    editor = null;
     document.getElementById("menu").style.visibility = 'visible';
     document.getElementById("endMenu").style.visibility = 'hidden';
-    positions = [];
-    faceColors = [];
-    indices = [];
+    document.getElementById("pauseMenu").style.visibility = 'hidden';
+    document.getElementById("countdown").innerHTML = "";
+    showHudViews(0);
+    positions = new Float32Array(0);
+    colors = new Float32Array(0);
+    normals = new Float32Array(0);
+    indices = new Uint16Array(0);
+    batches = new Uint32Array(0);
   }
 
   const restart = () => {
     universe.restart();
-    master.set_mode(Mode.Play);
+    master.set_mode(universe.counting_down() ? Mode.Countdown : Mode.Play);
     document.getElementById("menu").style.visibility = 'hidden';
     document.getElementById("endMenu").style.visibility = 'hidden';
-    startTime = Date.now();
+    document.getElementById("pauseMenu").style.visibility = 'hidden';
+    document.getElementById("countdown").innerHTML = "";
   }
 
+  // pausing freezes the run; the simulation isn't stepped until resumed
+  const pauseMenu = document.getElementById("pauseMenu");
+  pauseMenu.style.visibility = 'hidden';
+  const pause = () => {
+    if (!inRun() || !master.pause(universe)) {
+      return;
+    }
+    // the universe let go of held inputs, so forget them here too
+    for (const pad in gamepadState) {
+      delete gamepadState[pad];
+    }
+    document.exitPointerLock();
+    pauseMenu.style.visibility = 'visible';
+  };
+  const resume = () => {
+    if (!master.resume(universe)) {
+      return;
+    }
+    pauseMenu.style.visibility = 'hidden';
+    document.body.requestPointerLock();
+  };
+  document.getElementById("resume").onclick = resume;
+
+  // Look and view settings, from the menu or mid-run from the pause menu,
+  // where they apply straight away.
+  const settingsMenu = document.getElementById("settingsMenu");
+  settingsMenu.style.visibility = 'hidden';
+  let settingsFrom = null; // the menu to go back to
+  const SLIDERS = [
+    ["sensitivity", () => master.sensitivity(), value => master.set_sensitivity(value), value => value.toFixed(2) + "x"],
+    ["sensitivityX", () => master.sensitivity_x(), value => master.set_sensitivity_x(value), value => value.toFixed(2) + "x"],
+    ["sensitivityY", () => master.sensitivity_y(), value => master.set_sensitivity_y(value), value => value.toFixed(2) + "x"],
+    ["fov", () => master.fov(), value => master.set_fov(value), value => value.toFixed(0) + "\u00b0"],
+  ];
+  const CHECKBOXES = [
+    ["invertY", () => master.invert_y(), checked => master.set_invert_y(checked)],
+    ["headBob", () => master.head_bob(), checked => master.set_head_bob(checked)],
+  ];
+  const settingChanged = () => {
+    if (universe) {
+      master.apply_settings(universe);
+    }
+    persist();
+  };
+  for (const [id, get, set, show] of SLIDERS) {
+    const slider = document.getElementById(id);
+    const label = document.getElementById(id + "Value");
+    slider.oninput = () => {
+      set(parseFloat(slider.value));
+      label.innerHTML = show(get());
+      settingChanged();
+    };
+  }
+  for (const [id, get, set] of CHECKBOXES) {
+    const checkbox = document.getElementById(id);
+    checkbox.onchange = () => {
+      set(checkbox.checked);
+      settingChanged();
+    };
+  }
+  const showSettings = (from) => {
+    for (const [id, get, set, show] of SLIDERS) {
+      document.getElementById(id).value = get();
+      document.getElementById(id + "Value").innerHTML = show(get());
+    }
+    for (const [id, get] of CHECKBOXES) {
+      document.getElementById(id).checked = get();
+    }
+    settingsFrom = from;
+    from.style.visibility = 'hidden';
+    settingsMenu.style.visibility = 'visible';
+  };
+  document.getElementById("settings").onclick = () => showSettings(document.getElementById("menu"));
+  document.getElementById("pauseSettings").onclick = () => showSettings(pauseMenu);
+  document.getElementById("settingsBack").onclick = () => {
+    settingsMenu.style.visibility = 'hidden';
+    settingsFrom.style.visibility = 'visible';
+  };
+  document.getElementById("pauseRestart").onclick = () => restart();
+  document.getElementById("pauseQuit").onclick = () => goMenu();
+  document.addEventListener("pointerlockchange", () => {
+    if (document.pointerLockElement !== document.body) {
+      pause();
+    }
+  });
+  document.addEventListener("visibilitychange", () => {
+    if (document.hidden) {
+      pause();
+    }
+  });
+
+  const FLY_KEYS = {
+    "a": Fly.Left,
+    "w": Fly.Forward,
+    "d": Fly.Right,
+    "s": Fly.Back,
+    " ": Fly.Up,
+    "c": Fly.Down,
+  };
+  // x, y, z grid steps; with shift these resize instead of move
+  const NUDGE_KEYS = {
+    "ArrowLeft": [-1, 0, 0],
+    "ArrowRight": [1, 0, 0],
+    "ArrowUp": [0, 0, 1],
+    "ArrowDown": [0, 0, -1],
+    "PageUp": [0, 1, 0],
+    "PageDown": [0, -1, 0],
+  };
+
   document.addEventListener('keydown', function(event) {
-    if (master.mode() == Mode.Play) {
-      if (event.defaultPrevented) {
-        return; // Do nothing if the event was already processed
+    if (rebinding) {
+      if (event.code === "Escape") {
+        rebinding = null;
+        controlsStatus.innerHTML = "";
+      } else if (rebinding.device !== Device.Gamepad) {
+        rebind(Control.key(event.code));
       }
+      event.preventDefault();
+      return;
+    }
 
-      // this is automatic in at least chrome but just in case
-      if (event.key === "m") {
+    if (master.mode() == Mode.Edit) {
+      const key = event.key.length === 1 ? event.key.toLowerCase() : event.key;
+      if (key in FLY_KEYS) {
+        editor.start_fly(FLY_KEYS[key]);
+      } else if (key in NUDGE_KEYS) {
+        const [dx, dy, dz] = NUDGE_KEYS[key];
+        if (event.shiftKey) {
+          editor.resize_selected(dx, dy, dz);
+        } else {
+          editor.move_selected(dx, dy, dz);
+        }
+      } else if (event.repeat) {
+        // the rest are one shot actions
+      } else if (key === "Delete" || key === "Backspace") {
+        editor.delete_selected();
+      } else if (key === "v") {
+        editor.duplicate_selected();
+      } else if (key === "n") {
+        editor.cycle_material();
+      } else if (key === "b") {
+        editor.set_win();
+      } else if (key === "h") {
+        editor.set_spawn();
+      } else if (key === "z") {
+        editor.undo();
+      } else if (key === "y") {
+        editor.redo();
+      } else if (key === "[") {
+        editor.set_grid(editor.grid() / 2);
+      } else if (key === "]") {
+        editor.set_grid(editor.grid() * 2);
+      } else if (key === "e") {
+        const data = editor.export();
+        console.log(data);
+        if (navigator.clipboard) {
+          navigator.clipboard.writeText(data);
+        }
+      } else if (key === "t") {
+        playtest();
+      } else if (key === "m") {
         goMenu();
       }
+      event.preventDefault();
+      return;
+    }
 
-      if (event.key ==="r") {
-        // restart level
-        restart();
+    if (master.mode() == Mode.Play && editor && event.key === "t") {
+      backToEditor();
+      return;
+    }
+
+    if (inRun()) {
+      if (event.defaultPrevented) {
+        return; // Do nothing if the event was already processed
       }
 
-      if (event.key in MOVE) {
-        //universe.go(MOVE[event.key]);
-        if (event.key !== " ") {
-          universe.player_input(myPlayer, MOVE[event.key]);
-        } else {
-          universe.player_input(myPlayer, INPUT["jump"]);
+      // practice: digits load a save slot, shift+digit saves to it
+      if (universe.practice()) {
+        const slot = ["Digit1", "Digit2", "Digit3", "Digit4"].indexOf(event.code);
+        if (slot !== -1) {
+          if (event.shiftKey) {
+            universe.save_state(slot);
+          } else {
+            universe.load_state(slot);
+          }
+        }
+        if (event.key === "[") {
+          universe.set_time_scale(universe.time_scale() / 2);
+        } else if (event.key === "]") {
+          universe.set_time_scale(universe.time_scale() * 2);
+        } else if (event.key === "p") {
+          universe.set_frame_advance(!universe.frame_advance());
+        } else if (event.key === "f") {
+          universe.advance_frame();
         }
       }
 
-      if (/[0]/.test(event.key)) {
-        myPlayer = parseInt(event.key);
-      }
+      press(lookup(Control.key(event.code)), null, true, event.repeat);
 
       event.preventDefault();
     }
   });
 
   document.addEventListener('keyup', function(event) {
-    if (master.mode() == Mode.Play) {
+    if (master.mode() == Mode.Edit) {
+      const key = event.key.length === 1 ? event.key.toLowerCase() : event.key;
+      if (key in FLY_KEYS) {
+        editor.stop_fly(FLY_KEYS[key]);
+      }
+      return;
+    }
+
+    if (inRun()) {
       if (event.defaultPrevented) {
         return; // Do nothing if the event was already processed
       }
 
-      if (event.key in MOVE) {
-        //universe.stop(MOVE[event.key]);
-        if (event.key !== " ") {
-          universe.player_input(myPlayer, MOVE[event.key] + 4);
-        }
-        
-      }
+      press(lookup(Control.key(event.code)), null, false, false);
 
       event.preventDefault();
     }
@@ -427,48 +946,221 @@ function main() {
 
   const goEndMenu = () => {
     document.exitPointerLock();
-    const endTime = Date.now();
-    const levelTime = (endTime - startTime) / 1000;
-    document.getElementById("time").innerHTML = "Finished in " + levelTime + " seconds.";
+    // simulated time, so slow motion and frame advance don't skew it
+    const levelTime = universe.run_time() / 1000;
+    const practiceNote = universe.counts_for_records() ? "" : " (practice)";
+    let medalNote = "";
+    if (universe.counts_for_records() && currentLevel !== null) {
+      const medal = master.finish(currentLevel, universe);
+      if (medal !== Medal.None) {
+        medalNote = " " + MEDAL_NAMES[medal] + " medal!";
+      }
+      persist();
+      renderCatalog();
+    }
+    document.getElementById("time").innerHTML = "Finished in " + levelTime.toFixed(3) + " seconds." + practiceNote + medalNote;
     document.getElementById("endMenu").style.visibility = 'visible';
     master.set_mode(Mode.WonLevel);
   }
 
+  const readGraphics = (source = universe) => {
+    positions = new Float32Array(memory.buffer, source.positions_ptr(), source.positions_len());
+    colors = new Float32Array(memory.buffer, source.colors_ptr(), source.colors_len());
+    normals = new Float32Array(memory.buffer, source.normals_ptr(), source.normals_len());
+    if (source.u32_indices()) {
+      indices = new Uint32Array(memory.buffer, source.indices_ptr(), source.indices_len());
+    } else {
+      indices = new Uint16Array(memory.buffer, source.short_indices_ptr(), source.short_indices_len());
+    }
+    batches = new Uint32Array(memory.buffer, source.batches_ptr(), source.batches_len());
+
+    let pos = new Float32Array(memory.buffer, source.cam_pos_ptr(), 3);
+    let theta = source.cam_theta();
+    let phi = source.cam_phi();
+
+    cameraPosition = {
+      x: pos[0],
+      y: pos[1],
+      z: pos[2],
+    }
+    cameraAngle = {
+      theta: theta,
+      phi: phi,
+    }
+    light = {
+      sunDirection: Array.from(new Float32Array(memory.buffer, source.sun_dir_ptr(), 3)),
+      ambient: source.ambient(),
+    }
+    view = {
+      fieldOfView: source.fov(),
+      zNear: source.z_near(),
+      zFar: source.z_far(),
+      fogStart: source.fog_start(),
+      fogColor: Array.from(new Float32Array(memory.buffer, source.fog_color_ptr(), 4)),
+    }
+  };
+
+  // hook for sounds and effects; the sim doesn't depend on any of this
+  function handleEvent(event) {
+    switch (event.kind) {
+      case EventKind.CheckpointReached:
+        console.log("player " + event.player + " reached checkpoint " + (event.checkpoint + 1));
+        break;
+      case EventKind.Died:
+        console.log("player " + event.player + " died");
+        break;
+      case EventKind.Started:
+        goShownUntil = Date.now() + GO_SHOWN_FOR;
+        break;
+    }
+  }
+
+  const GRAPPLE_LABELS = {
+    [GrappleStatus.Ready]: "ready",
+    [GrappleStatus.Casting]: "casting",
+    [GrappleStatus.Hooked]: "hooked",
+    [GrappleStatus.HookedPlayer]: "hooked player",
+  };
+
+  const GO_SHOWN_FOR = 700; // ms
+  let goShownUntil = 0;
+
+  // each split-screen view gets its own reticle, text and goal marker,
+  // created the first time that view is shown
+  const hudViews = [];
+  const hudView = (v) => {
+    while (hudViews.length <= v) {
+      const view = {};
+      for (const part of ["reticle", "hud", "goal"]) {
+        view[part] = document.createElement("div");
+        view[part].className = part;
+        document.body.appendChild(view[part]);
+      }
+      hudViews.push(view);
+    }
+    return hudViews[v];
+  };
+  const showHudViews = (count) => {
+    hudViews.forEach((view, v) => {
+      for (const part of Object.values(view)) {
+        part.style.display = v < count ? "" : "none";
+      }
+    });
+  };
+
+  // text in the corner of the view, and a marker for the goal that sticks to
+  // the view's edge when the goal is off screen
+  const updateHud = (hud, v, viewport) => {
+    const view = hudView(v);
+    // viewports are fractions of the canvas, measured from the bottom left
+    const left = viewport[0] * gl.canvas.clientWidth;
+    const top = (1 - viewport[1] - viewport[3]) * gl.canvas.clientHeight;
+    const width = viewport[2] * gl.canvas.clientWidth;
+    const height = viewport[3] * gl.canvas.clientHeight;
+    view.reticle.style.left = (left + width / 2) + "px";
+    view.reticle.style.top = (top + height / 2) + "px";
+
+    const grappleText = (status, distance) => {
+      let text = GRAPPLE_LABELS[status];
+      if (status !== GrappleStatus.Ready) {
+        text += " " + distance.toFixed(1) + "/" + hud.grapple_length.toFixed(0);
+      }
+      return text;
+    };
+    const checkpoint = hud.checkpoint === undefined ? 0 : hud.checkpoint + 1;
+    view.hud.style.left = (left + 10) + "px";
+    view.hud.style.top = (top + 10) + "px";
+    view.hud.innerHTML =
+      "time " + (hud.time / 1000).toFixed(2) + "\n" +
+      "speed " + hud.speed.toFixed(1) + "\n" +
+      "left " + grappleText(hud.left_grapple, hud.left_grapple_distance) + "\n" +
+      "right " + grappleText(hud.right_grapple, hud.right_grapple_distance) + "\n" +
+      (hud.checkpoints > 0 ? "checkpoint " + checkpoint + "/" + hud.checkpoints + "\n" : "") +
+      "goal " + hud.goal_distance.toFixed(0);
+    // the countdown is shared by every view
+    if (v === 0) {
+      let countdown = "";
+      if (hud.countdown > 0) {
+        countdown = Math.ceil(hud.countdown / 1000);
+      } else if (Date.now() < goShownUntil) {
+        countdown = "Go!";
+      }
+      document.getElementById("countdown").innerHTML = countdown;
+    }
+
+    let x = hud.goal_x;
+    let y = hud.goal_y;
+    if (!hud.goal_on_screen) {
+      x *= 0.9;
+      y *= 0.9;
+    }
+    view.goal.style.left = (left + (x + 1) / 2 * width) + "px";
+    view.goal.style.top = (top + (1 - y) / 2 * height) + "px";
+  };
+
   function render() {
     requestAnimationFrame(render);
     const currTime = Date.now();
     let elapsedTime = currTime - lastDrawTime;
     //console.log(elapsedTime);
 
-    if (universe && universe.won_level() && master.mode() !== Mode.WonLevel) {
-      goEndMenu();
+    if (universe && universe.won_level() && master.mode() === Mode.Play) {
+      if (editor) {
+        backToEditor();
+      } else {
+        goEndMenu();
+      }
     }
 
     if (currTime >= lastDrawTime + FPS_THROTTLE) {
 
-      if (master.mode() === Mode.Play) {
-        universe.update(myPlayer, elapsedTime);
-        graphics = universe.graphics(myPlayer);
-        positions = graphics.positions();
-        faceColors = graphics.colors();
-        indices = graphics.indices();
-
-        let pos = graphics.cam_pos();//[1.0, 4.0, -9.0];//
-        let theta = graphics.cam_theta();//0.0;//
-        let phi = graphics.cam_phi();//0.5; //
-      
-        cameraPosition = {
-          x: pos[0],
-          y: pos[1],
-          z: pos[2],
+      if (inRun() || master.mode() === Mode.Paused) {
+        const playing = inRun();
+        if (playing) {
+          pollGamepads();
         }
-        cameraAngle = {
-          theta: theta,
-          phi: phi,
+
+        // copied, since stepping may grow wasm memory and detach views into it
+        const viewports = Array.from(new Float32Array(memory.buffer, universe.viewports_ptr(), universe.local_players() * 4));
+        const canvasAspect = gl.canvas.clientWidth / gl.canvas.clientHeight;
+        showHudViews(universe.local_players());
+        for (let v = 0; v < universe.local_players(); ++v) {
+          const viewport = viewports.slice(v * 4, v * 4 + 4);
+          universe.set_aspect(canvasAspect * viewport[2] / viewport[3]);
+          // step once, then only rebuild geometry for the other views
+          if (v === 0 && playing) {
+            universe.update(v, elapsedTime);
+            master.update_countdown(universe);
+            const eventCount = universe.take_events();
+            for (let i = 0; i < eventCount; ++i) {
+              const event = universe.event(i);
+              handleEvent(event);
+              event.free();
+            }
+          } else {
+            universe.draw(v);
+          }
+          if (playing) {
+            // hud points the camera at this view, so read it after drawing
+            const hud = universe.hud(v);
+            updateHud(hud, v, viewport);
+            hud.free();
+            // the reticle lights up when a cast would hook
+            const aim = universe.aim(v);
+            hudView(v).reticle.style.backgroundColor = aim.hit ? "white" : "black";
+            aim.free();
+          }
+          readGraphics();
+          drawScene(gl, programInfo, buffers, viewport);
         }
+      } else if (master.mode() === Mode.Edit) {
+        editor.set_aspect(gl.canvas.clientWidth / gl.canvas.clientHeight);
+        editor.update(elapsedTime);
+        readGraphics(editor);
+        drawScene(gl, programInfo, buffers, [0, 0, 1, 1]);
+      } else {
+        drawScene(gl, programInfo, buffers, [0, 0, 1, 1]);
       }
-
-      drawScene(gl, programInfo, buffers);
       lastDrawTime = Date.now();
     }
 